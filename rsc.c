@@ -1,40 +1,248 @@
+#include <ctype.h>
 #include <stdio.h>
 #include <stdlib.h>
+#include <string.h>
 #include <time.h>
 #include <math.h>
 
-void rsc_init() {
+// The runtime for compiled executables. The compiled program's rsc_main passes the context
+// pointer it was called with to each of these functions first; the JIT uses it to find the
+// runtime for the current run, but there's only one run here so it's always NULL.
+//
+// RSC_SEED and RSC_PRECISION may be defined by the compiler, eg. from the runtime section of
+// rscc.toml.
+#ifndef RSC_PRECISION
+#define RSC_PRECISION 2
+#endif
+
+// The statuses a program stops with when something goes wrong, the same as in compiler.rs.
+#define RSC_STEP_LIMIT 3
+#define RSC_DIVISION_BY_ZERO 6
+#define RSC_NAN 7
+#define RSC_END_OF_INPUT 8
+#define RSC_INVALID_INPUT 9
+#define RSC_INPUT_ERROR 10
+
+// rsc_input gives up after this many invalid entries in a row, like MAX_INVALID_ENTRIES.
+#define RSC_MAX_INVALID_ENTRIES 5
+
+// Scripted input from --input or --input-file, which rsc_input uses before prompting.
+static double *inputs = NULL;
+static size_t inputs_len = 0;
+static size_t inputs_pos = 0;
+
+static int prompt = 1;
+
+// Every value rsc_input reads is saved here, from --record.
+static FILE *record = NULL;
+
+void rsc_init(void *ctx) {
+#ifdef RSC_SEED
+    srand(RSC_SEED);
+#else
     srand(time(NULL));
+#endif
 }
 
-void rsc_out(double number) {
-    printf("%.2f\n", number);
+void rsc_out(void *ctx, double number) {
+    printf("%.*f\n", RSC_PRECISION, number);
 }
 
-double rsc_input() {
-    double val = 0;
-    int scan_result = 0;
+// Reads the value for an INP on the given line into value. Returns zero, or the status to stop
+// the program with if the input ends, there are too many invalid entries or reading fails.
+int rsc_input(void *ctx, int line, double *value) {
+    if (inputs_pos < inputs_len) {
+        *value = inputs[inputs_pos++];
+    } else {
+        int invalid = 0;
+
+        while (1) {
+            if (prompt) {
+                printf("Input: ");
+
+                if (fflush(stdout) == EOF) {
+                    return RSC_INPUT_ERROR;
+                }
+            }
+
+            char entry[1024];
+
+            if (fgets(entry, sizeof(entry), stdin) == NULL) {
+                return ferror(stdin) ? RSC_INPUT_ERROR : RSC_END_OF_INPUT;
+            }
+
+            // a line too long for the buffer counts as one invalid entry, so skip the rest of it
+            int too_long = strchr(entry, '\n') == NULL && !feof(stdin);
+
+            if (too_long) {
+                int c;
+                while ((c = getchar()) != '\n' && c != EOF);
+            }
+
+            // like read_input in emitter.rs, the line has to be one number apart from the
+            // whitespace around it, written the way Rust parses floats, so no hex
+            char *start = entry;
+            while (isspace((unsigned char) *start)) start++;
+
+            char *end = start + strlen(start);
+            while (end > start && isspace((unsigned char) end[-1])) end--;
+            *end = '\0';
 
-    while (scan_result != 1) {
-        printf("Input: ");
-        scan_result = scanf("%lf", &val);
+            char *parsed;
+            double number = strtod(start, &parsed);
+
+            if (!too_long && end > start && parsed == end && strpbrk(start, "xX") == NULL && isfinite(number)) {
+                *value = number;
+                break;
+            }
+
+            if (++invalid == RSC_MAX_INVALID_ENTRIES) {
+                return RSC_INVALID_INPUT;
+            }
 
-        if (scan_result != 1) {
             printf("Invalid entry, try again.\n");
+        }
+    }
+
+    if (record != NULL) {
+        // the shortest %g that reads back as the same value, like format_input in emitter.rs
+        char recorded[32] = "";
+
+        for (int precision = 1; precision <= 17; precision++) {
+            char candidate[32];
+            snprintf(candidate, sizeof(candidate), "%.*g", precision, *value);
+
+            if (strtod(candidate, NULL) == *value && (recorded[0] == '\0' || strlen(candidate) < strlen(recorded))) {
+                strcpy(recorded, candidate);
+            }
+        }
 
-            // clear invalid input
-            while (getchar() != '\n');
+        if (fprintf(record, "%s\n", recorded) < 0 || fflush(record) == EOF) {
+            return RSC_INPUT_ERROR;
         }
     }
 
-    return val;
+    return 0;
+}
+
+// Called when the program stops with one of the statuses above because of the given line.
+void rsc_error(void *ctx, int status, int line) {
+    const char *reason;
+
+    switch (status) {
+        case RSC_STEP_LIMIT: reason = "step limit exceeded"; break;
+        case RSC_DIVISION_BY_ZERO: reason = "division by zero"; break;
+        case RSC_NAN: reason = "result is not a number"; break;
+        case RSC_END_OF_INPUT: reason = "end of input"; break;
+        case RSC_INVALID_INPUT: reason = "too many invalid entries"; break;
+        case RSC_INPUT_ERROR: reason = "input error"; break;
+        default: reason = "runtime error"; break;
+    }
+
+    fflush(stdout);
+    fprintf(stderr, "%s at line %d\n", reason, line);
 }
 
 static int min = -0x10000;
 static int max = 0x10000;
 
-double rsc_rand() {
+double rsc_rand(void *ctx) {
     double div = 2 + rand() % 11;
     double val = (min + rand() % (max - min + 1)) / div;
     return round(val * 100) / 100;
 }
+
+// Adds the values in text to the scripted input. Values are separated by commas or whitespace,
+// and everything from a # to the end of the line is a comment, like the files gen-inputs writes.
+static int add_inputs(const char *text, const char *source) {
+    const char *p = text;
+
+    while (*p != '\0') {
+        if (*p == '#') {
+            while (*p != '\0' && *p != '\n') p++;
+        } else if (*p == ',' || *p == ' ' || *p == '\t' || *p == '\r' || *p == '\n') {
+            p++;
+        } else {
+            char *end;
+            double val = strtod(p, &end);
+
+            // INP never accepts infinities or NaN when typed in, so they can't be scripted either
+            if (end == p || !isfinite(val) || (*end != '\0' && strchr(", \t\r\n#", *end) == NULL)) {
+                fprintf(stderr, "Invalid input value in %s\n", source);
+                return 0;
+            }
+
+            inputs = realloc(inputs, (inputs_len + 1) * sizeof(double));
+            inputs[inputs_len++] = val;
+            p = end;
+        }
+    }
+
+    return 1;
+}
+
+static int add_inputs_from_file(const char *path) {
+    FILE *file = fopen(path, "r");
+
+    if (file == NULL) {
+        fprintf(stderr, "Couldn't read %s\n", path);
+        return 0;
+    }
+
+    size_t len = 0;
+    char *text = malloc(1);
+    char buffer[4096];
+    size_t read;
+
+    while ((read = fread(buffer, 1, sizeof(buffer), file)) > 0) {
+        text = realloc(text, len + read + 1);
+        memcpy(text + len, buffer, read);
+        len += read;
+    }
+
+    text[len] = '\0';
+    fclose(file);
+
+    int result = add_inputs(text, path);
+    free(text);
+    return result;
+}
+
+int rsc_main(void *ctx);
+
+int main(int argc, char **argv) {
+    for (int i = 1; i < argc; i++) {
+        const char *arg = argv[i];
+        const char *value = i + 1 < argc ? argv[i + 1] : NULL;
+
+        if (strcmp(arg, "--no-prompt") == 0) {
+            prompt = 0;
+        } else if (strcmp(arg, "--input") == 0 && value != NULL) {
+            if (!add_inputs(value, "--input")) return 1;
+            i++;
+        } else if (strcmp(arg, "--input-file") == 0 && value != NULL) {
+            if (!add_inputs_from_file(value)) return 1;
+            i++;
+        } else if (strcmp(arg, "--record") == 0 && value != NULL) {
+            record = fopen(value, "w");
+
+            if (record == NULL) {
+                fprintf(stderr, "Couldn't create %s\n", value);
+                return 1;
+            }
+
+            i++;
+        } else {
+            fprintf(stderr, "Usage: %s [--input VALUES] [--input-file FILE] [--no-prompt] [--record FILE]\n", argv[0]);
+            return 1;
+        }
+    }
+
+    int status = rsc_main(NULL);
+
+    if (record != NULL) {
+        fclose(record);
+    }
+
+    return status;
+}
@@ -1,13 +1,21 @@
-use std::{env, fs, str, mem};
+use std::{env, fs, str};
+use std::collections::HashMap;
+use std::io::{self, Write};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use clap::{Parser, Subcommand};
+use std::time::Duration;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use rscc::parser::Diagnostic;
+use rscc::config::Config;
+use rscc::debugger::Session;
+use rscc::emitter::{RscRuntime, StdRuntime};
+use rscc::interpreter::{Event, Machine};
+use rscc::lint::{Level, LintLevels, Warning};
+use rscc::profile::Profile;
+use rscc::parser::{Diagnostic, Instruction};
 use target_lexicon::Triple;
 use std::str::FromStr;
-use target_lexicon;
 
 #[derive(Parser, Debug)]
 #[command(
@@ -16,11 +24,45 @@ use target_lexicon;
     version=rscc::version_info::version(),
     about="The RSC (Reasonably Simple Computer) compiler"
 )]
-struct CLI {
+struct Cli {
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Debug, Args)]
+struct LintArgs {
+    #[arg(short='W', value_name="LINT", help="Warn about a lint or lint group, eg. -W pedagogy (may be given more than once)")]
+    warn: Vec<String>,
+
+    #[arg(short='A', value_name="LINT", help="Allow a lint or lint group, overriding rscc.toml (may be given more than once)")]
+    allow: Vec<String>,
+
+    #[arg(short='D', value_name="LINT", help="Treat a lint or lint group as an error (may be given more than once)")]
+    deny: Vec<String>,
+}
+
+// Limits on how long a program can run and how much it can print, for rscc run. The runtime
+// enforces these, while the compiled code enforces max_steps.
+struct Limits {
+    timeout: Option<Duration>,
+    max_output: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct InputArgs {
+    #[arg(long, value_name="VALUES", allow_hyphen_values=true, conflicts_with="input_file", help="Values for INP to read, in order, eg. 3,4.5,-1. INP prompts for more once they run out")]
+    input: Option<String>,
+
+    #[arg(long, value_name="FILE", help="Read values for INP from a file, separated by commas or newlines, eg. one saved with --record")]
+    input_file: Option<String>,
+
+    #[arg(long, help="Don't print the \"Input: \" prompt before reading a value")]
+    no_prompt: bool,
+
+    #[arg(long, value_name="FILE", help="Save every value INP reads to a file, so the run can be replayed with --input-file")]
+    record: Option<String>,
+}
+
 #[derive(Debug, Subcommand)]
 enum Commands {
     #[command(
@@ -31,8 +73,17 @@ enum Commands {
         #[arg(long, short, value_name="FILE", help="The file containing the program to compile")]
         file: String,
 
-        #[arg(long, short, value_name="OUTPUT_PATH", default_value=".", help="The directory into which build artifacts and the resulting compiled executable should be written")]
-        output_path: String,
+        #[arg(long, short, value_name="OUTPUT_PATH", help="The directory into which build artifacts and the resulting compiled executable should be written [default: output-path from rscc.toml, or .]")]
+        output_path: Option<String>,
+
+        #[arg(long, value_name="N", help="Stop the program with a status of 3 if it executes more than this many instructions")]
+        max_steps: Option<u64>,
+
+        #[arg(long, value_enum, default_value_t=Arithmetic::Ieee, help="What division by zero does: ieee gives inf, or NaN for 0 / 0, while trap stops the program with a status of 6, or 7 for any other NaN result")]
+        arithmetic: Arithmetic,
+
+        #[command(flatten)]
+        lints: LintArgs,
     },
 
     #[command(
@@ -42,6 +93,36 @@ enum Commands {
     Run {
         #[arg(long, short, value_name="FILE", help="The file containing the program to run")]
         file: String,
+
+        #[arg(long, value_enum, help="Whether to compile the program to machine code or interpret it. Defaults to jit, or interp when tracing")]
+        engine: Option<Engine>,
+
+        #[arg(long, help="Print every instruction executed to standard error")]
+        trace: bool,
+
+        #[arg(long, value_enum, default_value_t=TraceFormat::Text, help="How to print the trace")]
+        trace_format: TraceFormat,
+
+        #[arg(long, help="Count how many times each line runs and print the counts to standard error afterwards")]
+        profile: bool,
+
+        #[arg(long, value_name="N", help="Stop the program with a status of 3 if it executes more than this many instructions")]
+        max_steps: Option<u64>,
+
+        #[arg(long, value_enum, default_value_t=Arithmetic::Ieee, help="What division by zero does: ieee gives inf, or NaN for 0 / 0, while trap stops the program with a status of 6, or 7 for any other NaN result")]
+        arithmetic: Arithmetic,
+
+        #[arg(long, value_name="DURATION", value_parser=parse_duration, help="Stop the program with a status of 4 if it runs for longer than this, eg. 5s or 500ms")]
+        timeout: Option<Duration>,
+
+        #[arg(long, value_name="N", help="Stop the program with a status of 5 if it tries to print more than this many values")]
+        max_output: Option<u64>,
+
+        #[command(flatten)]
+        inputs: InputArgs,
+
+        #[command(flatten)]
+        lints: LintArgs,
     },
 
     #[command(
@@ -52,58 +133,793 @@ enum Commands {
     Check {
         #[arg(long, short, value_name="FILE", help="The file containing the program to check")]
         file: String,
-    }
+
+        #[command(flatten)]
+        lints: LintArgs,
+    },
+
+    #[command(
+        about="Show the instructions that can affect an OUT instruction",
+        long_about="Show the instructions that can affect the value printed by the OUT instruction on the given line, following the accumulator and memory through branches. The whole program is printed with those instructions highlighted.",
+        arg_required_else_help = true,
+    )]
+    Slice {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to slice")]
+        file: String,
+
+        #[arg(long, short, value_name="LINE", help="The line number of the OUT instruction to slice")]
+        line: usize,
+    },
+
+    #[command(
+        about="List the lines that read and write each memory location",
+        long_about="List every memory location the program uses, together with the lines that read it (LDA, ADD, SUB, MUL, DIV, OUT) and the lines that write it (STA, INP). Locations that are only ever read or only ever written are flagged.",
+        arg_required_else_help = true,
+    )]
+    Xref {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to cross-reference")]
+        file: String,
+    },
+
+    #[command(
+        about="Print size and complexity metrics for an RSC program",
+        arg_required_else_help = true,
+    )]
+    Stats {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to measure")]
+        file: String,
+
+        #[arg(long, value_enum, default_value_t=ReportFormat::Table, help="How to print the report")]
+        format: ReportFormat,
+    },
+
+    #[command(
+        about="Print the control-flow graph of an RSC program in Graphviz DOT format",
+        long_about="Print the control-flow graph of an RSC program in Graphviz DOT format. Each node is a basic block labeled with its source lines, and the edges out of BPA, BNA and BZA are labeled with their conditions. Render the output with eg. dot -Tpng prog.dot -o prog.png.",
+        arg_required_else_help = true,
+    )]
+    Graph {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to graph")]
+        file: String,
+    },
+
+    #[command(
+        about="Print an RSC program as structured pseudocode",
+        long_about="Print an RSC program as structured pseudocode, recovering if/else and while loops from its branches. Memory locations print as m[N], or as their names if they're listed in the names section of rscc.toml. Flow that can't be structured falls back to gotos.",
+        arg_required_else_help = true,
+    )]
+    Decompile {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to decompile")]
+        file: String,
+    },
+
+    #[command(
+        about="Print each OUT of a loop-free RSC program as a formula of its inputs",
+        long_about="Print each OUT of a loop-free RSC program as a formula of its inputs, eg. OUT#1 = (in1 + in2) / 2. The nth value read by INP is inN, and a memory location N that's read before it's written is the free symbol mN. Programs with branches print one set of formulas for each path through them.",
+        arg_required_else_help = true,
+    )]
+    Symbolic {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to analyze")]
+        file: String,
+    },
+
+    #[command(
+        about="Generate inputs that exercise every branch of an RSC program",
+        long_about="Generate inputs that exercise every branch of an RSC program. Runs the program on inputs drawn from small numbers, the constants it uses and tweaks of earlier inputs until each BPA, BNA and BZA has gone both ways. Prints one input vector per line, with comments saying which branches each one covers.",
+        arg_required_else_help = true,
+    )]
+    GenInputs {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to generate inputs for")]
+        file: String,
+
+        #[arg(long, default_value_t=1000, help="How many input vectors to try")]
+        trials: usize,
+
+        #[arg(long, help="Seed for the search, to make it repeatable. Defaults to the seed in rscc.toml")]
+        seed: Option<u64>,
+
+        #[arg(long, default_value_t=10_000, help="Abandon runs after executing this many instructions")]
+        max_steps: u64,
+    },
+
+    #[command(
+        about="Check that an RSC program prints the same outputs as a reference solution",
+        long_about="Check that an RSC program prints the same outputs as a reference solution. Both programs are run on the same inputs, either the ones in the given file (in the format printed by gen-inputs) or ones generated to cover the branches of both programs, and the first input on which they differ is reported. Outputs are compared as they'd be printed.",
+        arg_required_else_help = true,
+    )]
+    Compare {
+        #[arg(value_name="STUDENT", help="The file containing the program to check")]
+        student: String,
+
+        #[arg(value_name="REFERENCE", help="The file containing the reference solution")]
+        reference: String,
+
+        #[arg(long, default_value_t=100, help="How many input vectors to try if none are given")]
+        trials: usize,
+
+        #[arg(long, help="Seed for generating inputs and uninitialized memory, to make the comparison repeatable. Defaults to the seed in rscc.toml")]
+        seed: Option<u64>,
+
+        #[arg(long, default_value_t=10_000, help="Treat runs that execute more than this many instructions as not terminating")]
+        max_steps: u64,
+
+        #[arg(long, value_name="FILE", help="A file of input vectors to compare the programs on, one per line")]
+        inputs: Option<String>,
+    },
+
+    #[command(
+        about="Score how similar the RSC programs in a directory are to each other",
+        long_about="Score how similar the RSC programs in a directory are to each other, to help spot copied submissions. Programs are normalized so that comments, whitespace, line numbers and the choice of memory locations don't matter, then every pair is scored by the runs of instructions they share. Pairs are listed most similar first.",
+        arg_required_else_help = true,
+    )]
+    Similarity {
+        #[arg(value_name="DIR", help="The directory containing the .rsc files to compare")]
+        dir: String,
+
+        #[arg(long, default_value_t=rscc::similarity::DEFAULT_NGRAM, help="How many consecutive instructions to compare at a time")]
+        ngram: usize,
+
+        #[arg(long, default_value_t=0.0, help="Only list pairs at least this similar, as a percentage")]
+        threshold: f64,
+    },
+
+    #[command(
+        about="Explain an RSC program in plain English",
+        long_about="Explain an RSC program in plain English, without running it. Each line is described on its own, followed by summaries of the program's loops and of the lines that branches skip over. Memory locations use their names from the names section of rscc.toml where known.",
+        arg_required_else_help = true,
+    )]
+    Describe {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to describe")]
+        file: String,
+    },
+
+    #[command(
+        about="Step through an RSC program interactively",
+        long_about="Step through an RSC program interactively. Set breakpoints by line, label or address, step or continue, print and change the accumulator and memory, watch memory locations for writes and queue input values. Type help at the (rscdb) prompt for a list of commands.",
+        arg_required_else_help = true,
+    )]
+    Debug {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to debug")]
+        file: String,
+    },
+
+    #[command(
+        about="Run a Debug Adapter Protocol server over stdin and stdout",
+        long_about="Run a Debug Adapter Protocol server over stdin and stdout, so editors like VS Code can debug RSC programs. The program to debug is given by the program attribute of the launch request.",
+    )]
+    Dap,
+
+    #[command(
+        about="Watch an RSC program run in a full-screen terminal view",
+        long_about="Watch an RSC program run in a full-screen terminal view, with the source, memory, accumulator and input and output side by side. Step forwards and backwards, run at different speeds or reset the program from the keyboard.",
+        arg_required_else_help = true,
+    )]
+    Tui {
+        #[arg(long, short, value_name="FILE", help="The file containing the program to run")]
+        file: String,
+    },
+
+    #[command(
+        about="Run RSC instructions interactively, one at a time",
+        long_about="Run RSC instructions interactively, one at a time. Each instruction runs as soon as it's entered, against an accumulator and memory that persist between lines. Type :help at the prompt for a list of meta-commands.",
+    )]
+    Repl {
+        #[arg(long, short, value_name="FILE", help="A program to load and run first")]
+        file: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Engine {
+    Interp,
+    Jit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Arithmetic {
+    // IEEE 754, where eg. 1 / 0 is inf and 0 / 0 is NaN
+    Ieee,
+
+    // Stop the program on division by zero or a NaN result
+    Trap,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TraceFormat {
+    Text,
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Table,
+    Json,
 }
 
 fn main() -> ExitCode {
-    let options = CLI::parse();
+    let options = Cli::parse();
 
     match options.command {
-        Commands::Build { file, output_path } => {
-            build(&file, &output_path)
+        Commands::Build { file, output_path, max_steps, arithmetic, lints } => {
+            let options = rscc::compiler::CompileOptions {
+                max_steps,
+                trap_arithmetic: arithmetic == Arithmetic::Trap,
+                ..Default::default()
+            };
+
+            build(&file, output_path, &options, &lints)
+        }
+
+        Commands::Run { file, engine, trace, trace_format, profile, max_steps, arithmetic, timeout, max_output, inputs, lints } => {
+            let trace_format = if trace { Some(trace_format) } else { None };
+
+            let options = rscc::compiler::CompileOptions {
+                profile,
+                max_steps,
+                trap_arithmetic: arithmetic == Arithmetic::Trap,
+                checks: timeout.is_some() || max_output.is_some(),
+            };
+
+            let limits = Limits { timeout, max_output };
+            run(&file, engine, trace_format, &options, &limits, &inputs, &lints)
+        }
+
+        Commands::Check { file, lints } => {
+            check(&file, &lints)
+        }
+
+        Commands::Slice { file, line } => {
+            slice(&file, line)
+        }
+
+        Commands::Xref { file } => {
+            xref(&file)
+        }
+
+        Commands::Stats { file, format } => {
+            stats(&file, format)
+        }
+
+        Commands::Graph { file } => {
+            graph(&file)
+        }
+
+        Commands::Decompile { file } => {
+            decompile(&file)
         }
 
-        Commands::Run { file } => {
-            run(&file)
+        Commands::Symbolic { file } => {
+            symbolic(&file)
         }
 
-        Commands::Check { file } => {
-            check(&file)
+        Commands::GenInputs { file, trials, seed, max_steps } => {
+            gen_inputs(&file, trials, seed, max_steps)
+        }
+
+        Commands::Compare { student, reference, trials, seed, max_steps, inputs } => {
+            compare(&student, &reference, trials, seed, max_steps, inputs)
+        }
+
+        Commands::Similarity { dir, ngram, threshold } => {
+            similarity(&dir, ngram, threshold)
+        }
+
+        Commands::Describe { file } => {
+            describe(&file)
+        }
+
+        Commands::Debug { file } => {
+            debug(&file)
+        }
+
+        Commands::Dap => {
+            dap()
+        }
+
+        Commands::Tui { file } => {
+            tui(&file)
+        }
+
+        Commands::Repl { file } => {
+            repl(file.as_deref())
         }
     }
 }
 
-fn build(file: &str, output_path: &str) -> ExitCode {
-    match parse_file_and_diagnose(file) {
+fn build(file: &str, output_path: Option<String>, options: &rscc::compiler::CompileOptions, lints: &LintArgs) -> ExitCode {
+    let config = match load_config(file) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    match parse_file_and_diagnose(file, &config, lints) {
         Some(parse_result) => {
-            build_instrs(file, output_path, parse_result.instructions)
+            let output_path = output_path
+                .or(config.output_path.clone())
+                .unwrap_or(".".to_string());
+
+            build_instrs(file, &output_path, &config.runtime_options(), options, parse_result.instructions)
         }
 
         None => ExitCode::from(1)
     }
 }
 
-fn run(file: &str) -> ExitCode {
-    match parse_file_and_diagnose(file) {
+fn run(file: &str, engine: Option<Engine>, trace_format: Option<TraceFormat>, options: &rscc::compiler::CompileOptions, limits: &Limits, inputs: &InputArgs, lints: &LintArgs) -> ExitCode {
+    let config = match load_config(file) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    // compiled code runs without stopping between instructions, so only the interpreter can trace
+    let engine = match (engine, trace_format) {
+        (Some(Engine::Jit), Some(_)) => {
+            println!("Tracing is only supported by the interp engine");
+            return ExitCode::from(1);
+        }
+
+        (Some(engine), _) => engine,
+        (None, Some(_)) => Engine::Interp,
+        (None, None) => Engine::Jit
+    };
+
+    match parse_file_and_diagnose(file, &config, lints) {
         Some(parse_result) => {
-            run_instrs(parse_result.instructions)
+            let values = match scripted_inputs(inputs) {
+                Ok(values) => values,
+                Err(message) => {
+                    println!("{}", message);
+                    return ExitCode::from(1);
+                }
+            };
+
+            let runtime_options = rscc::emitter::RuntimeOptions {
+                timeout: limits.timeout,
+                max_output: limits.max_output,
+                inputs: values,
+                no_prompt: inputs.no_prompt,
+                record: inputs.record.clone(),
+                ..config.runtime_options()
+            };
+
+            let mut runtime = match StdRuntime::new(&runtime_options) {
+                Ok(runtime) => runtime,
+                Err(message) => {
+                    println!("{}", message);
+                    return ExitCode::from(1);
+                }
+            };
+
+            match engine {
+                Engine::Interp => interpret_instrs(&parse_result, &mut runtime, runtime_options.seed, trace_format, options),
+                Engine::Jit => run_instrs(&parse_result, &mut runtime, options)
+            }
         }
 
         None => ExitCode::from(1)
     }
 }
 
-fn check(file: &str) -> ExitCode {
-    match parse_file_and_diagnose(file) {
+fn check(file: &str, lints: &LintArgs) -> ExitCode {
+    let config = match load_config(file) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    match parse_file_and_diagnose(file, &config, lints) {
         Some(_) => ExitCode::from(0),
         None => ExitCode::from(1)
     }
 }
 
-fn build_instrs(file: &str, output_path: &str, instructions: Vec<rscc::parser::Instruction>) -> ExitCode {
-    let rsc_module = rscc::emitter::emit_object_module(
+fn slice(file: &str, line: usize) -> ExitCode {
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            match rscc::slice::slice_output(&parse_result.instructions, line) {
+                Ok(slice) => {
+                    println!("{}", rscc::slice::annotate(&parse_result.code, &parse_result.instructions, &slice));
+                    ExitCode::from(0)
+                }
+
+                Err(message) => {
+                    println!("{}", message);
+                    ExitCode::from(1)
+                }
+            }
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn xref(file: &str) -> ExitCode {
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            let refs = rscc::xref::xref(&parse_result.instructions);
+            println!("{}", rscc::xref::render(&refs));
+            ExitCode::from(0)
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn stats(file: &str, format: ReportFormat) -> ExitCode {
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            let stats = rscc::stats::stats(&parse_result.instructions);
+
+            match format {
+                ReportFormat::Table => println!("{}", rscc::stats::render_table(&stats)),
+                ReportFormat::Json => println!("{}", rscc::stats::render_json(&stats))
+            }
+
+            ExitCode::from(0)
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn graph(file: &str) -> ExitCode {
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            println!("{}", rscc::graph::to_dot(&parse_result.code, &parse_result.instructions));
+            ExitCode::from(0)
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn decompile(file: &str) -> ExitCode {
+    let names = match load_names(file) {
+        Some(names) => names,
+        None => return ExitCode::from(1)
+    };
+
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            println!("{}", rscc::decompile::decompile(&parse_result.instructions, &names));
+            ExitCode::from(0)
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn symbolic(file: &str) -> ExitCode {
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            match rscc::symbolic::execute(&parse_result.instructions) {
+                Ok(paths) => {
+                    println!("{}", rscc::symbolic::render(&paths));
+                    ExitCode::from(0)
+                }
+
+                Err(message) => {
+                    println!("{}", message);
+                    ExitCode::from(1)
+                }
+            }
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn gen_inputs(file: &str, trials: usize, seed: Option<u64>, max_steps: u64) -> ExitCode {
+    let config = match load_config(file) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            let options = rscc::coverage::SearchOptions {
+                trials,
+                seed: seed.or(config.runtime_options().seed),
+                max_steps,
+            };
+
+            let coverage = rscc::coverage::generate(&parse_result.instructions, &options);
+            println!("{}", rscc::coverage::render(&parse_result.instructions, &coverage));
+            ExitCode::from(0)
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn compare(student: &str, reference: &str, trials: usize, seed: Option<u64>, max_steps: u64, inputs: Option<String>) -> ExitCode {
+    let config = match load_config(student) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    let vectors = match inputs {
+        Some(path) => {
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(error) => {
+                    println!("Couldn't read {}: {}", path, error);
+                    return ExitCode::from(1);
+                }
+            };
+
+            match rscc::coverage::parse_vectors(&text) {
+                Ok(vectors) => Some(vectors),
+                Err(message) => {
+                    println!("Invalid input vectors in {}: {}", path, message);
+                    return ExitCode::from(1);
+                }
+            }
+        }
+
+        None => None
+    };
+
+    let (student_result, reference_result) = match (parse_for_analysis(student), parse_for_analysis(reference)) {
+        (Some(student_result), Some(reference_result)) => (student_result, reference_result),
+        _ => return ExitCode::from(1)
+    };
+
+    let runtime_options = config.runtime_options();
+
+    let options = rscc::compare::CompareOptions {
+        trials,
+        seed: seed.or(runtime_options.seed),
+        max_steps,
+        precision: runtime_options.precision.unwrap_or(rscc::emitter::DEFAULT_PRECISION),
+    };
+
+    match rscc::compare::compare(&student_result.instructions, &reference_result.instructions, vectors, &options) {
+        rscc::compare::Verdict::Same(trials) => {
+            println!("The programs printed the same outputs for all {} input vectors", trials);
+            ExitCode::from(0)
+        }
+
+        rscc::compare::Verdict::Different(mismatch) => {
+            println!("{}", rscc::compare::render(&mismatch, student, reference, &options));
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn similarity(dir: &str, ngram: usize, threshold: f64) -> ExitCode {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            println!("Couldn't read {}: {}", dir, error);
+            return ExitCode::from(1);
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "rsc"))
+        .collect();
+
+    paths.sort();
+
+    let mut names = vec![];
+    let mut programs = vec![];
+
+    for path in paths {
+        let file = path.to_string_lossy();
+        let config = match load_config(&file) {
+            Some(config) => config,
+            None => return ExitCode::from(1)
+        };
+
+        let parse_result = match parse_file(&file, &config) {
+            Ok(parse_result) => parse_result,
+            Err(code) => return code
+        };
+
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+
+        // one broken submission shouldn't stop the rest from being compared
+        if parse_result.diagnostics.len() > 0 {
+            println!("Skipping {}, which has {} compilation problem(s)", name, parse_result.diagnostics.len());
+            continue;
+        }
+
+        names.push(name);
+        programs.push(rscc::similarity::normalize(&parse_result.instructions));
+    }
+
+    let pairs: Vec<rscc::similarity::Pair> = rscc::similarity::pairwise(&programs, ngram.max(1))
+        .into_iter()
+        .filter(|pair| pair.score * 100.0 >= threshold)
+        .collect();
+
+    println!("{}", rscc::similarity::render(&names, &pairs));
+    ExitCode::from(0)
+}
+
+fn describe(file: &str) -> ExitCode {
+    let names = match load_names(file) {
+        Some(names) => names,
+        None => return ExitCode::from(1)
+    };
+
+    match parse_for_analysis(file) {
+        Some(parse_result) => {
+            println!("{}", rscc::describe::describe(&parse_result.instructions, &names));
+            ExitCode::from(0)
+        }
+
+        None => ExitCode::from(1)
+    }
+}
+
+fn debug(file: &str) -> ExitCode {
+    let config = match load_config(file) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    let names = match load_names(file) {
+        Some(names) => names,
+        None => return ExitCode::from(1)
+    };
+
+    let parse_result = match parse_for_analysis(file) {
+        Some(parse_result) => parse_result,
+        None => return ExitCode::from(1)
+    };
+
+    let runtime_options = config.runtime_options();
+    let precision = runtime_options.precision.unwrap_or(rscc::emitter::DEFAULT_PRECISION);
+
+    let mut session = Session::new(&parse_result.code, &parse_result.instructions, runtime_options.seed, precision)
+        .with_names(names, config.labels.clone());
+
+    println!("Debugging {}. Type help for a list of commands.\n", file);
+    println!("{}", session.context());
+
+    // an empty line repeats the last command, like gdb
+    let mut last_command = String::new();
+
+    loop {
+        print!("(rscdb) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => return ExitCode::from(0),
+            Ok(_) => {}
+        }
+
+        let line = match line.trim() {
+            "" => last_command.clone(),
+            line => line.to_string()
+        };
+
+        if line == "quit" || line == "q" {
+            return ExitCode::from(0);
+        }
+
+        // inputs come from the input command, so the prompt isn't mixed up with program input
+        match session.command(&line, &mut || None) {
+            Ok(reply) => println!("{}", reply),
+            Err(message) => println!("{}", message.red())
+        }
+
+        last_command = line;
+    }
+}
+
+fn dap() -> ExitCode {
+    // stdout carries the protocol, so nothing else can be printed there, and color codes would end
+    // up in the editor
+    colored::control::set_override(false);
+
+    match rscc::dap::serve(io::BufReader::new(io::stdin()), &mut io::stdout()) {
+        Ok(()) => ExitCode::from(0),
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn tui(file: &str) -> ExitCode {
+    let config = match load_config(file) {
+        Some(config) => config,
+        None => return ExitCode::from(1)
+    };
+
+    let parse_result = match parse_for_analysis(file) {
+        Some(parse_result) => parse_result,
+        None => return ExitCode::from(1)
+    };
+
+    let runtime_options = config.runtime_options();
+    let precision = runtime_options.precision.unwrap_or(rscc::emitter::DEFAULT_PRECISION);
+    let title = Path::new(file).file_name().unwrap().to_string_lossy().to_string();
+    let mut app = rscc::tui::App::new(&title, &parse_result.code, &parse_result.instructions, runtime_options.seed, precision);
+
+    match rscc::tui::run(&mut app) {
+        Ok(()) => ExitCode::from(0),
+        Err(message) => {
+            println!("{}", message);
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn repl(file: Option<&str>) -> ExitCode {
+    let config = match file {
+        Some(file) => match load_config(file) {
+            Some(config) => config,
+            None => return ExitCode::from(1)
+        },
+
+        None => Config::default()
+    };
+
+    let runtime_options = config.runtime_options();
+    let precision = runtime_options.precision.unwrap_or(rscc::emitter::DEFAULT_PRECISION);
+    let mut repl = rscc::repl::Repl::new(config.parse_options(), runtime_options.seed, precision);
+
+    // INP prompts for its value, asking again until it gets a number
+    let mut read_input = |location: u32| loop {
+        print!("Input for m[{}]: ", location);
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+
+        match line.trim().parse::<f64>() {
+            Ok(value) => return Some(value),
+            Err(_) => println!("{}", format!("'{}' is not a number", line.trim()).red())
+        }
+    };
+
+    println!("RSC REPL. Type :help for a list of commands.");
+
+    if let Some(file) = file {
+        match repl.eval(&format!(":load {}", file), &mut read_input) {
+            Ok(reply) => println!("{}", reply),
+            Err(message) => println!("{}", message.red())
+        }
+    }
+
+    loop {
+        print!("{}> ", repl.next_line());
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => return ExitCode::from(0),
+            Ok(_) => {}
+        }
+
+        if line.trim() == ":quit" || line.trim() == ":q" {
+            return ExitCode::from(0);
+        }
+
+        match repl.eval(&line, &mut read_input) {
+            Ok(reply) if reply.len() == 0 => {}
+            Ok(reply) => println!("{}", reply),
+            Err(message) => println!("{}", message.red())
+        }
+    }
+}
+
+fn build_instrs(file: &str, output_path: &str, runtime_options: &rscc::emitter::RuntimeOptions, options: &rscc::compiler::CompileOptions, instructions: Vec<rscc::parser::Instruction>) -> ExitCode {
+    let rsc_module = rscc::emitter::emit_object_module_with_options(
         Triple::from_str(rscc::built_info::TARGET).unwrap(),
-        instructions
+        instructions,
+        options
     );
 
     let path = Path::new(file);
@@ -112,7 +928,7 @@ fn build_instrs(file: &str, output_path: &str, instructions: Vec<rscc::parser::I
 
     fs::create_dir_all(&out_dir).unwrap();
 
-    let o_file = out_dir.join(&format!("{}.o", base_name));
+    let o_file = out_dir.join(format!("{}.o", base_name));
     let mut file = File::create(o_file.as_os_str()).unwrap();
     rsc_module.product.object.write_stream(&mut file).unwrap();
 
@@ -128,6 +944,14 @@ fn build_instrs(file: &str, output_path: &str, instructions: Vec<rscc::parser::I
 
     modify_path_if_necessary();
 
+    if let Some(seed) = runtime_options.seed {
+        build.define("RSC_SEED", Some(format!("{}u", seed as u32).as_str()));
+    }
+
+    if let Some(precision) = runtime_options.precision {
+        build.define("RSC_PRECISION", Some(precision.to_string().as_str()));
+    }
+
     build.file(c_helper)
         .object(o_file)
         .target(rscc::built_info::TARGET)
@@ -158,22 +982,184 @@ fn build_instrs(file: &str, output_path: &str, instructions: Vec<rscc::parser::I
     }
 }
 
-fn run_instrs(instructions: Vec<rscc::parser::Instruction>) -> ExitCode {
-    let rsc_module = rscc::emitter::emit_jit_module(
-        instructions,
-        None
-    );
+// Compiles and runs the program, then prints how many times each line ran if profiling.
+fn run_instrs(parse_result: &rscc::parser::ParseResult, runtime: &mut dyn RscRuntime, options: &rscc::compiler::CompileOptions) -> ExitCode {
+    let rsc_module = rscc::emitter::emit_jit_module_with_options(parse_result.instructions.clone(), options);
+    let status = rsc_module.run(runtime);
 
-    let main = rsc_module.module.get_finalized_function(rsc_module.main_id);
-    let code_fn = unsafe { mem::transmute::<_, fn() -> ()>(main) };
+    if let Some(counts) = rsc_module.counts() {
+        let profile = Profile::from_counts(&parse_result.instructions, counts);
+        eprintln!("{}", rscc::profile::render(&parse_result.code, &profile));
+    }
 
-    code_fn();
+    ExitCode::from(status as u8)
+}
 
-    ExitCode::from(0)
+// Runs the program in the interpreter, honoring the same options as the JIT.
+fn interpret_instrs(parse_result: &rscc::parser::ParseResult, runtime: &mut dyn RscRuntime, seed: Option<u64>, trace_format: Option<TraceFormat>, options: &rscc::compiler::CompileOptions) -> ExitCode {
+    let instructions = &parse_result.instructions;
+    let mut machine = Machine::new(instructions, seed);
+    let mut counts = Profile::new(instructions);
+    let mut status = 0;
+
+    runtime.start();
+
+    // limits are checked at the start of each basic block, like the JIT does
+    let block_lens: HashMap<usize, u64> = rscc::cfg::basic_blocks(instructions).iter()
+        .map(|block| (block.start, block.len() as u64))
+        .collect();
+
+    while let Some(idx) = machine.pc {
+        let instr = &instructions[idx];
+        let line = instr.lineno();
+
+        if let Some(len) = block_lens.get(&idx) {
+            if options.max_steps.is_some_and(|max_steps| machine.steps + len > max_steps) {
+                status = rscc::compiler::STEP_LIMIT_STATUS;
+                runtime.error(status, line);
+                break;
+            }
+
+            if options.checks {
+                status = runtime.check(line);
+
+                if status != 0 {
+                    break;
+                }
+            }
+        }
+
+        counts.record(idx);
+
+        // errors that stop an instruction from running are found before it runs, like in the JIT
+        let input = match instr {
+            Instruction::INP(_) => match runtime.input() {
+                Ok(value) => value,
+                Err(error) => {
+                    status = error;
+                    runtime.error(status, line);
+                    break;
+                }
+            },
+
+            Instruction::DIV(div) if options.trap_arithmetic && machine.read(div.location) == 0.0 => {
+                status = rscc::compiler::DIVISION_BY_ZERO_STATUS;
+                runtime.error(status, line);
+                break;
+            }
+
+            _ => 0.0
+        };
+
+        if let Some(format) = trace_format {
+            let record = rscc::trace::step(&mut machine, &mut || input).unwrap();
+
+            if let Some(value) = record.output {
+                runtime.output(value);
+            }
+
+            match format {
+                TraceFormat::Text => eprintln!("{}", rscc::trace::render_text(&record)),
+                TraceFormat::Jsonl => eprintln!("{}", rscc::trace::render_json(&record))
+            }
+        } else {
+            match machine.step() {
+                Event::Output(value) => runtime.output(value),
+                Event::InputRequest(_) => machine.input(input),
+                Event::Halted | Event::Executed => {}
+            }
+        }
+
+        let arithmetic = matches!(instr, Instruction::ADC(_) | Instruction::ADD(_) | Instruction::SUB(_) | Instruction::MUL(_) | Instruction::DIV(_));
+
+        if options.trap_arithmetic && arithmetic && machine.accum.is_nan() {
+            status = rscc::compiler::NAN_STATUS;
+            runtime.error(status, line);
+            break;
+        }
+    }
+
+    // a program that printed too much after the last check still has to report it
+    if status == 0 && options.checks {
+        let line = instructions.last().map_or(0, |instr| instr.lineno());
+        status = runtime.check(line);
+    }
+
+    if options.profile {
+        eprintln!("{}", rscc::profile::render(&parse_result.code, &counts));
+    }
+
+    ExitCode::from(status as u8)
 }
 
-fn parse_file_and_diagnose(file: &str) -> Option<rscc::parser::ParseResult> {
-    match parse_file(file) {
+// The values given by --input or --input-file, in the format gen-inputs prints.
+fn scripted_inputs(inputs: &InputArgs) -> Result<Vec<f64>, String> {
+    let vectors = match (&inputs.input, &inputs.input_file) {
+        (Some(values), _) => rscc::coverage::parse_vectors(values)
+            .map_err(|message| format!("Invalid --input: {}", message))?,
+
+        (None, Some(path)) => {
+            let text = fs::read_to_string(path).map_err(|error| format!("Couldn't read {}: {}", path, error))?;
+            rscc::coverage::parse_vectors(&text).map_err(|message| format!("Invalid input values in {}: {}", path, message))?
+        }
+
+        (None, None) => vec![]
+    };
+
+    let values = vectors.concat();
+
+    // INP never accepts these when typed in, so they can't be scripted either
+    match values.iter().find(|value| !value.is_finite()) {
+        Some(value) => Err(format!("Invalid input value '{}'; INP only accepts finite numbers", value)),
+        None => Ok(values)
+    }
+}
+
+// Accepts eg. 500ms, 5s, 1.5m, or a bare number of seconds.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(number) = text.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = text.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = text.strip_suffix('m') {
+        (number, 60.0)
+    } else {
+        (text, 1.0)
+    };
+
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok(Duration::from_secs_f64(number * scale)),
+        _ => Err(format!("'{}' is not a duration; try eg. 5s or 500ms", text))
+    }
+}
+
+fn load_config(file: &str) -> Option<Config> {
+    match Config::discover(Path::new(file)) {
+        Ok(config) => Some(config),
+        Err(message) => {
+            println!("Invalid configuration: {}", message);
+            None
+        }
+    }
+}
+
+// The names for memory locations from the names section of rscc.toml.
+fn load_names(file: &str) -> Option<HashMap<u32, String>> {
+    match load_config(file)?.names() {
+        Ok(names) => Some(names),
+        Err(message) => {
+            println!("Invalid configuration: {}", message);
+            None
+        }
+    }
+}
+
+// Parses a file for the analysis subcommands, which honor the dialect settings in rscc.toml but
+// don't run any lints.
+fn parse_for_analysis(file: &str) -> Option<rscc::parser::ParseResult> {
+    let config = load_config(file)?;
+
+    match parse_file(file, &config) {
         Ok(parse_result) => {
             if parse_result.diagnostics.len() > 0 {
                 print_diagnostics(&parse_result.diagnostics, &parse_result.code);
@@ -187,7 +1173,51 @@ fn parse_file_and_diagnose(file: &str) -> Option<rscc::parser::ParseResult> {
     }
 }
 
-fn parse_file(file: &str) -> Result<rscc::parser::ParseResult, ExitCode> {
+// Lint levels from rscc.toml, overridden by any given on the command line.
+fn lint_levels(config: &Config, lints: &LintArgs) -> Result<LintLevels, String> {
+    let mut levels = config.lint_levels()?;
+
+    levels.set_all(&lints.allow, Level::Allow)?;
+    levels.set_all(&lints.warn, Level::Warn)?;
+    levels.set_all(&lints.deny, Level::Deny)?;
+
+    Ok(levels)
+}
+
+fn parse_file_and_diagnose(file: &str, config: &Config, lints: &LintArgs) -> Option<rscc::parser::ParseResult> {
+    let levels = match lint_levels(config, lints) {
+        Ok(levels) => levels,
+        Err(message) => {
+            println!("{}", message);
+            return None;
+        }
+    };
+
+    match parse_file(file, config) {
+        Ok(parse_result) => {
+            if parse_result.diagnostics.len() > 0 {
+                print_diagnostics(&parse_result.diagnostics, &parse_result.code);
+                None
+            } else {
+                let warnings = rscc::lint::check(&parse_result.instructions, &levels.enabled());
+
+                if warnings.len() > 0 {
+                    print_warnings(&warnings, &levels, &parse_result.code);
+                }
+
+                if warnings.iter().any(|warning| levels.level(warning.lint) == Level::Deny) {
+                    None
+                } else {
+                    Some(parse_result)
+                }
+            }
+        }
+
+        Err(_) => None
+    }
+}
+
+fn parse_file(file: &str, config: &Config) -> Result<rscc::parser::ParseResult, ExitCode> {
     let path = Path::new(file);
 
     match fs::exists(path) {
@@ -206,7 +1236,7 @@ fn parse_file(file: &str) -> Result<rscc::parser::ParseResult, ExitCode> {
 
     match fs::read_to_string(path) {
         Ok(contents) => {
-            Ok(rscc::parser::parse(&contents))
+            Ok(rscc::parser::parse_with_options(&contents, &config.parse_options()))
         }
 
         Err(e) => {
@@ -216,7 +1246,7 @@ fn parse_file(file: &str) -> Result<rscc::parser::ParseResult, ExitCode> {
     }
 }
 
-fn print_diagnostics(diagnostics: &Vec<Diagnostic>, code: &str) {
+fn print_diagnostics(diagnostics: &[Diagnostic], code: &str) {
     println!("Found {} compilation problem(s)\n", diagnostics.len());
 
     for (idx, diagnostic) in diagnostics.iter().enumerate() {
@@ -225,6 +1255,20 @@ fn print_diagnostics(diagnostics: &Vec<Diagnostic>, code: &str) {
     }
 }
 
+fn print_warnings(warnings: &[Warning], levels: &LintLevels, code: &str) {
+    println!("Found {} warning(s)\n", warnings.len());
+
+    for (idx, warning) in warnings.iter().enumerate() {
+        if levels.level(warning.lint) == Level::Deny {
+            println!("{}", format!("------------ DENIED WARNING {} ------------", idx + 1).red());
+        } else {
+            println!("{}", format!("-------------- WARNING {} ---------------", idx + 1).yellow());
+        }
+
+        println!("{}\n", warning.annotate(code));
+    }
+}
+
 fn find_c_helper_file() -> Option<PathBuf> {
     let found = match &mut env::current_exe() {
         Ok(exe_path) => {
@@ -257,24 +1301,20 @@ fn find_c_helper_file() -> Option<PathBuf> {
 }
 
 fn modify_path_if_necessary() {
-    match &mut env::current_exe() {
-        Ok(exe_path) => {
-            exe_path.pop();
-            let mingw_path = exe_path.join("mingw64_rsc").join("bin");
+    if let Ok(exe_path) = &mut env::current_exe() {
+        exe_path.pop();
+        let mingw_path = exe_path.join("mingw64_rsc").join("bin");
 
-            if mingw_path.exists() {
-                match std::env::var("PATH") {
-                    Ok(existing_path) => {
-                        std::env::set_var("PATH", format!("{};{}", existing_path, mingw_path.to_str().unwrap()));
-                    }
+        if mingw_path.exists() {
+            match std::env::var("PATH") {
+                Ok(existing_path) => {
+                    std::env::set_var("PATH", format!("{};{}", existing_path, mingw_path.to_str().unwrap()));
+                }
 
-                    Err(_) => {
-                        std::env::set_var("PATH", mingw_path.as_os_str());
-                    }
+                Err(_) => {
+                    std::env::set_var("PATH", mingw_path.as_os_str());
                 }
             }
         }
-
-        Err(_) => ()
-    };
+    }
 }
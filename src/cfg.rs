@@ -0,0 +1,364 @@
+use std::collections::BTreeSet;
+use crate::parser::{self, Instruction};
+
+// Control flow between individual instructions. Instructions are identified by their index in the
+// instruction list, which is not the same as their line number.
+
+// Returns the indices of the instructions that may execute immediately after the given one.
+// Branches whose target lies past the end of the program have no successor on that edge; see
+// leaves_program.
+pub fn successors(instructions: &[Instruction], idx: usize) -> Vec<usize> {
+    let next = if idx + 1 < instructions.len() {
+        Some(idx + 1)
+    } else {
+        None
+    };
+
+    let target = |location: u32| parser::target_index(instructions, location);
+
+    let mut succs: Vec<usize> = match &instructions[idx] {
+        Instruction::STP(_) => vec![],
+        Instruction::BRU(bru) => target(bru.location).into_iter().collect(),
+        Instruction::BPA(bpa) => next.into_iter().chain(target(bpa.location)).collect(),
+        Instruction::BNA(bna) => next.into_iter().chain(target(bna.location)).collect(),
+        Instruction::BZA(bza) => next.into_iter().chain(target(bza.location)).collect(),
+        _ => next.into_iter().collect()
+    };
+
+    succs.dedup();
+    succs
+}
+
+// Whether the program can end right after the given instruction other than by STP, by branching
+// past the last line or by running off the end.
+pub fn leaves_program(instructions: &[Instruction], idx: usize) -> bool {
+    let last = idx + 1 == instructions.len();
+    let past_end = instructions[idx].branch_target()
+        .is_some_and(|location| parser::target_index(instructions, location).is_none());
+
+    match &instructions[idx] {
+        Instruction::STP(_) => false,
+        Instruction::BRU(_) => past_end,
+        _ => last || past_end
+    }
+}
+
+pub fn predecessors(instructions: &[Instruction]) -> Vec<Vec<usize>> {
+    let mut preds = vec![vec![]; instructions.len()];
+
+    for idx in 0..instructions.len() {
+        for succ in successors(instructions, idx) {
+            preds[succ].push(idx);
+        }
+    }
+
+    preds
+}
+
+// Computes the set of instructions that post-dominate each instruction, i.e. that lie on every
+// path from the instruction to the end of the program. Every instruction post-dominates itself.
+// The end is a virtual exit node after STP and after every edge that leaves the program, which
+// nothing else post-dominates. Instructions that can never reach the end (eg. inside an infinite
+// loop) are post-dominated by everything.
+pub fn post_dominators(instructions: &[Instruction]) -> Vec<BTreeSet<usize>> {
+    compute_post_dominators(instructions, true)
+}
+
+// Like post_dominators, but ignoring edges that leave the program other than STP, so the sides of
+// a branch meet where the ones that stay in the program do. decompile joins branches this way,
+// since a side that leaves is just a stop.
+pub fn post_dominators_within(instructions: &[Instruction]) -> Vec<BTreeSet<usize>> {
+    compute_post_dominators(instructions, false)
+}
+
+fn compute_post_dominators(instructions: &[Instruction], with_exits: bool) -> Vec<BTreeSet<usize>> {
+    let all: BTreeSet<usize> = (0..instructions.len()).collect();
+    let succs: Vec<Vec<usize>> = (0..instructions.len()).map(|idx| successors(instructions, idx)).collect();
+    let exits: Vec<bool> = (0..instructions.len()).map(|idx| with_exits && leaves_program(instructions, idx)).collect();
+    let mut pdoms: Vec<BTreeSet<usize>> = vec![all; instructions.len()];
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for idx in (0..instructions.len()).rev() {
+            let mut new_pdoms = if succs[idx].len() == 0 || exits[idx] {
+                BTreeSet::new()
+            } else {
+                let mut iter = succs[idx].iter();
+                let first = pdoms[*iter.next().unwrap()].clone();
+
+                iter.fold(first, |acc, succ| {
+                    acc.intersection(&pdoms[*succ]).copied().collect()
+                })
+            };
+
+            new_pdoms.insert(idx);
+
+            if new_pdoms != pdoms[idx] {
+                pdoms[idx] = new_pdoms;
+                changed = true;
+            }
+        }
+    }
+
+    pdoms
+}
+
+// Returns, for each instruction, the conditional branches that decide whether it executes. An
+// instruction is control dependent on a branch if one of the branch's edges always leads to the
+// instruction but the other may bypass it, including by leaving the program.
+pub fn control_dependences(instructions: &[Instruction]) -> Vec<BTreeSet<usize>> {
+    let pdoms = post_dominators(instructions);
+    let mut deps = vec![BTreeSet::new(); instructions.len()];
+
+    for branch in 0..instructions.len() {
+        let succs = successors(instructions, branch);
+
+        if succs.len() + (leaves_program(instructions, branch) as usize) < 2 {
+            continue;
+        }
+
+        for succ in succs {
+            for idx in &pdoms[succ] {
+                if *idx == branch || !pdoms[branch].contains(idx) {
+                    deps[*idx].insert(branch);
+                }
+            }
+        }
+    }
+
+    deps
+}
+
+// A run of instructions that always execute together, from start up to but not including end.
+// Only the first instruction can be jumped to and only the last can jump elsewhere. Successors are
+// block indices.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub succs: Vec<usize>,
+
+    // Whether the block can also end the program without an STP, see leaves_program.
+    pub exits: bool,
+}
+
+impl BasicBlock {
+    pub fn len(self: &Self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(self: &Self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn last(self: &Self) -> usize {
+        self.end - 1
+    }
+}
+
+// Splits the program into basic blocks. A new block starts at the first instruction, at every
+// branch target, and after every branch or STP.
+pub fn basic_blocks(instructions: &[Instruction]) -> Vec<BasicBlock> {
+    if instructions.len() == 0 {
+        return vec![];
+    }
+
+    let mut leaders = BTreeSet::from([0]);
+
+    for (idx, instr) in instructions.iter().enumerate() {
+        if let Some(location) = instr.branch_target() {
+            leaders.extend(parser::target_index(instructions, location));
+        }
+
+        if instr.branch_target().is_some() || matches!(instr, Instruction::STP(_)) {
+            leaders.insert(idx + 1);
+        }
+    }
+
+    let starts: Vec<usize> = leaders.into_iter().filter(|idx| *idx < instructions.len()).collect();
+    let mut block_of = vec![0; instructions.len()];
+
+    let mut blocks: Vec<BasicBlock> = starts.iter().enumerate().map(|(block_idx, start)| {
+        let end = starts.get(block_idx + 1).copied().unwrap_or(instructions.len());
+
+        block_of[*start..end].fill(block_idx);
+
+        BasicBlock { start: *start, end, succs: vec![], exits: false }
+    }).collect();
+
+    for block in blocks.iter_mut() {
+        block.succs = successors(instructions, block.last())
+            .into_iter()
+            .map(|idx| block_of[idx])
+            .collect();
+
+        block.exits = leaves_program(instructions, block.last());
+    }
+
+    blocks
+}
+
+// Returns the index of the block containing the given instruction.
+pub fn block_containing(blocks: &[BasicBlock], idx: usize) -> Option<usize> {
+    blocks.iter().position(|block| block.start <= idx && idx < block.end)
+}
+
+// Describes the condition under which a conditional branch is taken, eg. "acc > 0", or the
+// condition under which it falls through if negated is true. Returns None for anything other
+// than BPA, BNA and BZA.
+pub fn branch_condition(instr: &Instruction, negated: bool) -> Option<&'static str> {
+    match (instr, negated) {
+        (Instruction::BPA(_), false) => Some("acc > 0"),
+        (Instruction::BPA(_), true) => Some("acc <= 0"),
+        (Instruction::BNA(_), false) => Some("acc < 0"),
+        (Instruction::BNA(_), true) => Some("acc >= 0"),
+        (Instruction::BZA(_), false) => Some("acc == 0"),
+        (Instruction::BZA(_), true) => Some("acc != 0"),
+        _ => None
+    }
+}
+
+// Returns the indices of the blocks that can be reached from the first one.
+pub fn reachable(blocks: &[BasicBlock]) -> BTreeSet<usize> {
+    let mut seen = BTreeSet::new();
+    let mut worklist = if blocks.len() > 0 { vec![0] } else { vec![] };
+
+    while let Some(block_idx) = worklist.pop() {
+        if seen.insert(block_idx) {
+            worklist.extend(&blocks[block_idx].succs);
+        }
+    }
+
+    seen
+}
+
+// A loop formed by one or more branches back to the same header block. The body includes the
+// header.
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: usize,
+    pub body: BTreeSet<usize>,
+}
+
+// Finds loops by looking for branches back to a block that is still being visited in a depth-first
+// walk from the start of the program. Loops are returned in order of their header block.
+pub fn loops(blocks: &[BasicBlock]) -> Vec<Loop> {
+    if blocks.len() == 0 {
+        return vec![];
+    }
+
+    let mut back_edges: Vec<(usize, usize)> = vec![];
+    let mut on_stack = vec![false; blocks.len()];
+    let mut visited = vec![false; blocks.len()];
+
+    // iterative depth-first search; each stack entry is a block and the index of its next successor
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    on_stack[0] = true;
+
+    while let Some((block_idx, succ_idx)) = stack.pop() {
+        if succ_idx < blocks[block_idx].succs.len() {
+            stack.push((block_idx, succ_idx + 1));
+            let succ = blocks[block_idx].succs[succ_idx];
+
+            if on_stack[succ] {
+                back_edges.push((block_idx, succ));
+            } else if !visited[succ] {
+                visited[succ] = true;
+                on_stack[succ] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            on_stack[block_idx] = false;
+        }
+    }
+
+    let mut preds = vec![vec![]; blocks.len()];
+
+    for (block_idx, block) in blocks.iter().enumerate() {
+        for succ in &block.succs {
+            preds[*succ].push(block_idx);
+        }
+    }
+
+    let mut loops: Vec<Loop> = vec![];
+
+    for (tail, header) in back_edges {
+        // the body is everything that can reach the tail without going through the header
+        let mut body = BTreeSet::from([header]);
+        let mut worklist = vec![tail];
+
+        while let Some(block_idx) = worklist.pop() {
+            if body.insert(block_idx) {
+                worklist.extend(&preds[block_idx]);
+            }
+        }
+
+        match loops.iter_mut().find(|l| l.header == header) {
+            Some(existing) => existing.body.extend(body),
+            None => loops.push(Loop { header, body })
+        }
+    }
+
+    loops.sort_by_key(|l| l.header);
+    loops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_follows_branches() {
+        let result = parser::parse("LDC 1\nBPA 4\nBRU 1\nSTP");
+        let instrs = &result.instructions;
+
+        assert!(successors(instrs, 0) == vec![1]);
+        assert!(successors(instrs, 1) == vec![2, 3]);
+        assert!(successors(instrs, 2) == vec![0]);
+        assert!(successors(instrs, 3).len() == 0);
+        assert!(predecessors(instrs)[0] == vec![2]);
+    }
+
+    #[test]
+    fn it_splits_basic_blocks() {
+        let result = parser::parse("INP 10\nLDA 10\nBPA 5\nLDC 0\nSTA 11\nOUT 11\nSTP");
+        let blocks = basic_blocks(&result.instructions);
+
+        assert!(blocks.len() == 3);
+        assert!((blocks[0].start, blocks[0].end) == (0, 3));
+        assert!(blocks[0].succs == vec![1, 2]);
+        assert!((blocks[1].start, blocks[1].end) == (3, 4));
+        assert!(blocks[1].succs == vec![2]);
+        assert!((blocks[2].start, blocks[2].end) == (4, 7));
+        assert!(blocks[2].succs.len() == 0);
+    }
+
+    #[test]
+    fn it_finds_nested_loops() {
+        let result = parser::parse(
+            "INP 10\nINP 11\nLDA 11\nADC -1\nSTA 11\nBPA 3\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP"
+        );
+
+        let blocks = basic_blocks(&result.instructions);
+        let loops = loops(&blocks);
+
+        assert!(loops.len() == 2);
+        assert!(loops[0].body.len() == 3);
+        assert!(loops[1].body.len() == 1);
+        assert!(loops[0].body.is_superset(&loops[1].body));
+    }
+
+    #[test]
+    fn it_finds_control_dependences() {
+        // line 3 only runs if the branch on line 2 falls through
+        let result = parser::parse("INP 10\nBPA 4\nOUT 10\nSTP");
+        let deps = control_dependences(&result.instructions);
+
+        assert!(deps[0].len() == 0);
+        assert!(deps[2] == BTreeSet::from([1]));
+        assert!(deps[3].len() == 0);
+    }
+}
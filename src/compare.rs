@@ -0,0 +1,224 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::coverage::{self, SearchOptions};
+use crate::emitter::DEFAULT_PRECISION;
+use crate::interpreter::{Event, Machine};
+use crate::parser::Instruction;
+
+// Checks a program against a reference solution by running both on the same inputs and comparing
+// what they print. Outputs are compared as they'd be printed, so programs that compute the same
+// answer with slightly different rounding error still match.
+
+pub struct CompareOptions {
+    // The number of input vectors to try when none are supplied.
+    pub trials: usize,
+    pub seed: Option<u64>,
+
+    // Runs that execute more instructions than this are treated as not terminating.
+    pub max_steps: u64,
+    pub precision: usize,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        CompareOptions { trials: 100, seed: None, max_steps: 10_000, precision: DEFAULT_PRECISION }
+    }
+}
+
+// What one program did with one input vector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub outputs: Vec<String>,
+
+    // False if the run was stopped at the step limit.
+    pub finished: bool,
+}
+
+impl Run {
+    fn matches(self: &Self, other: &Run) -> bool {
+        match (self.finished, other.finished) {
+            (true, true) => self.outputs == other.outputs,
+
+            // neither finished, so only compare what they'd printed by the time they were stopped
+            (false, false) => self.outputs.iter().zip(&other.outputs).all(|(a, b)| a == b),
+
+            _ => false
+        }
+    }
+}
+
+// The first input vector the programs disagree on.
+#[derive(Debug)]
+pub struct Mismatch {
+    pub inputs: Vec<f64>,
+    pub left: Run,
+    pub right: Run,
+}
+
+pub enum Verdict {
+    // The programs agreed on this many input vectors.
+    Same(usize),
+    Different(Mismatch),
+}
+
+// Runs the program, reading inputs from the start of the vector and appending more with
+// next_value once it runs out.
+pub fn run(instructions: &[Instruction], inputs: &mut Vec<f64>, seed: u64, options: &CompareOptions, mut next_value: impl FnMut() -> f64) -> Run {
+    let mut machine = Machine::new(instructions, Some(seed));
+    let mut outputs = vec![];
+    let mut consumed = 0;
+
+    while machine.steps < options.max_steps {
+        match machine.step() {
+            Event::Output(value) => outputs.push(format!("{:.*}", options.precision, value)),
+
+            Event::InputRequest(_) => {
+                if consumed == inputs.len() {
+                    inputs.push(next_value());
+                }
+
+                machine.input(inputs[consumed]);
+                consumed += 1;
+            }
+
+            Event::Halted => return Run { outputs, finished: true },
+            Event::Executed => {}
+        }
+    }
+
+    Run { outputs, finished: false }
+}
+
+// Compares the programs on the given input vectors, or if there are none, on vectors that cover
+// the branches of both programs followed by random ones. Vectors are extended with random values
+// if a program reads more inputs than they contain.
+pub fn compare(left: &[Instruction], right: &[Instruction], vectors: Option<Vec<Vec<f64>>>, options: &CompareOptions) -> Verdict {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy()
+    };
+
+    let vectors = match vectors {
+        Some(vectors) => vectors,
+        None => {
+            let search = SearchOptions { trials: options.trials, seed: Some(rng.gen()), max_steps: options.max_steps };
+            let mut vectors: Vec<Vec<f64>> = vec![];
+
+            for instructions in [right, left] {
+                for vector in coverage::generate(instructions, &search).vectors {
+                    if !vectors.contains(&vector.inputs) {
+                        vectors.push(vector.inputs);
+                    }
+                }
+            }
+
+            // the rest are filled in as the programs ask for input
+            vectors.resize(vectors.len().max(options.trials), vec![]);
+            vectors
+        }
+    };
+
+    let mut pool = coverage::interesting_values(left);
+    pool.extend(coverage::interesting_values(right));
+
+    let trials = vectors.len();
+
+    for mut inputs in vectors {
+        // both programs see the same uninitialized memory for a given trial
+        let seed = rng.gen();
+        let right_run = run(right, &mut inputs, seed, options, || coverage::pick_value(&mut rng, &pool));
+        let left_run = run(left, &mut inputs, seed, options, || coverage::pick_value(&mut rng, &pool));
+
+        if !left_run.matches(&right_run) {
+            return Verdict::Different(Mismatch { inputs, left: left_run, right: right_run });
+        }
+    }
+
+    Verdict::Same(trials)
+}
+
+// Describes a mismatch, naming the programs by their file names.
+pub fn render(mismatch: &Mismatch, left_name: &str, right_name: &str, options: &CompareOptions) -> String {
+    let inputs: Vec<String> = mismatch.inputs.iter().map(|value| value.to_string()).collect();
+
+    let describe = |run: &Run| {
+        let outputs = if run.outputs.len() == 0 {
+            "nothing".to_string()
+        } else {
+            run.outputs.join(", ")
+        };
+
+        if run.finished {
+            outputs
+        } else {
+            format!("{} before being stopped after {} steps", outputs, options.max_steps)
+        }
+    };
+
+    let width = left_name.len().max(right_name.len());
+
+    [
+        if inputs.len() == 0 {
+            "The programs differ when given no input:".to_string()
+        } else {
+            format!("The programs differ on input {}:", inputs.join(","))
+        },
+        format!("  {:<width$}  printed {}", left_name, describe(&mismatch.left)),
+        format!("  {:<width$}  printed {}", right_name, describe(&mismatch.right)),
+    ].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    // averages two inputs
+    const REFERENCE: &str = "INP 10\nINP 11\nLDC 2\nSTA 12\nLDA 10\nADD 11\nDIV 12\nSTA 13\nOUT 13\nSTP";
+
+    fn compare_programs(left: &str, right: &str, vectors: Option<Vec<Vec<f64>>>) -> Verdict {
+        let left = parser::parse(left);
+        let right = parser::parse(right);
+        let options = CompareOptions { seed: Some(1), ..CompareOptions::default() };
+
+        compare(&left.instructions, &right.instructions, vectors, &options)
+    }
+
+    #[test]
+    fn it_accepts_equivalent_programs() {
+        // divides each input by two before adding
+        let student = "LDC 2\nSTA 12\nINP 10\nINP 11\nLDA 10\nDIV 12\nSTA 10\nLDA 11\nDIV 12\nADD 10\nSTA 13\nOUT 13\nSTP";
+        assert!(matches!(compare_programs(student, REFERENCE, None), Verdict::Same(_)));
+    }
+
+    #[test]
+    fn it_finds_differing_inputs() {
+        // forgets to divide when the first input is negative
+        let student = "INP 10\nINP 11\nLDC 2\nSTA 12\nLDA 10\nBNA 9\nADD 11\nDIV 12\nBRU 11\nADD 11\nSTA 13\nOUT 13\nSTP";
+
+        match compare_programs(student, REFERENCE, None) {
+            Verdict::Different(mismatch) => {
+                assert!(mismatch.inputs.len() == 2);
+                assert!(mismatch.inputs[0] < 0.0);
+                assert!(mismatch.left.outputs != mismatch.right.outputs);
+            }
+
+            Verdict::Same(_) => panic!("expected the programs to differ")
+        }
+    }
+
+    #[test]
+    fn it_stops_programs_that_never_finish() {
+        let student = "INP 10\nINP 11\nBRU 3\nSTP";
+
+        match compare_programs(student, REFERENCE, Some(vec![vec![3.0, 4.0]])) {
+            Verdict::Different(mismatch) => {
+                assert!(mismatch.inputs == vec![3.0, 4.0]);
+                assert!(!mismatch.left.finished);
+                assert!(mismatch.right == Run { outputs: vec!["3.50".to_string()], finished: true });
+            }
+
+            Verdict::Same(_) => panic!("expected the programs to differ")
+        }
+    }
+}
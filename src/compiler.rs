@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 use cranelift::prelude::*;
-use cranelift_codegen::ir::{FuncRef, Function};
+use cranelift_codegen::ir::{FuncRef, Function, StackSlot};
 use cranelift_codegen::Context;
-use cranelift_module::{FuncId, Linkage, Module};
-use crate::parser::Instruction;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use crate::cfg;
+use crate::parser::{self, Instruction};
 
 struct Program<'a, M: Module> {
     module: &'a mut M,
@@ -11,25 +12,42 @@ struct Program<'a, M: Module> {
     rsc_out: FuncRef,
     rsc_rand: FuncRef,
     rsc_input: FuncRef,
+    rsc_error: FuncRef,
+
+    // Where rsc_input writes the value it reads.
+    input_slot: StackSlot,
+
+    // The context pointer main was called with, which is passed on to every runtime function.
+    ctx: Value,
+
     accum: Variable,
     location_vars: HashMap<u32, Variable>,
     var_index: usize,
+
+    // The address of the rsc_counts data object, when profiling.
+    counts: Option<Value>,
+
+    // The number of instructions left to run, when there's a step limit.
+    steps_left: Option<Variable>,
+
+    // Whether to stop on division by zero and NaN results. See CompileOptions.
+    trap_arithmetic: bool,
+
+    // The rsc_check function, when the runtime checks limits of its own.
+    rsc_check: Option<FuncRef>,
 }
 
 impl<'a, M: Module> Program<'a, M> {
-    fn new(main: &mut FunctionBuilder, module: &'a mut M) -> Self {
-        main.func.signature.call_conv = module.isa().default_call_conv();
-        main.func.signature.returns.push(AbiParam::new(types::I32));
-
+    fn new(main: &mut FunctionBuilder, module: &'a mut M, ctx: Value) -> Self {
         // Declare the rsc_init function
-        let init_sig = module.make_signature();
+        let init_sig = runtime_signature(module);
 
         let init_func_id = module
             .declare_function("rsc_init", Linkage::Import, &init_sig)
             .unwrap();
 
         // Declare the rsc_out function
-        let mut out_sig = module.make_signature();
+        let mut out_sig = runtime_signature(module);
         out_sig.params.push(AbiParam::new(types::F64));
 
         let out_func_id = module
@@ -37,21 +55,34 @@ impl<'a, M: Module> Program<'a, M> {
             .unwrap();
 
         // Declare the rsc_rand function
-        let mut rand_sig = module.make_signature();
+        let mut rand_sig = runtime_signature(module);
         rand_sig.returns.push(AbiParam::new(types::F64));
 
         let rand_func_id = module
             .declare_function("rsc_rand", Linkage::Import, &rand_sig)
             .unwrap();
 
-        // Declare the rsc_input function
-        let mut input_sig = module.make_signature();
-        input_sig.returns.push(AbiParam::new(types::F64));
+        // Declare the rsc_input function, which takes the line number and a pointer to write the
+        // value to, and returns zero or the status to stop with
+        let mut input_sig = runtime_signature(module);
+        input_sig.params.push(AbiParam::new(types::I32));
+        input_sig.params.push(AbiParam::new(module.target_config().pointer_type()));
+        input_sig.returns.push(AbiParam::new(types::I32));
 
         let input_func_id = module
             .declare_function("rsc_input", Linkage::Import, &input_sig)
             .unwrap();
 
+        // Declare the rsc_error function, which takes a status and a line number
+        let mut error_sig = runtime_signature(module);
+        error_sig.params.push(AbiParam::new(types::I32));
+        error_sig.params.push(AbiParam::new(types::I32));
+
+        let error_func_id = module
+            .declare_function("rsc_error", Linkage::Import, &error_sig)
+            .unwrap();
+
+        let input_slot = main.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 8, 3));
         let accum = Variable::new(0);
 
         Self {
@@ -59,10 +90,17 @@ impl<'a, M: Module> Program<'a, M> {
             rsc_out: module.declare_func_in_func(out_func_id, main.func),
             rsc_rand: module.declare_func_in_func(rand_func_id, main.func),
             rsc_input: module.declare_func_in_func(input_func_id, main.func),
+            rsc_error: module.declare_func_in_func(error_func_id, main.func),
+            input_slot,
             module,
-            accum: accum,
+            ctx,
+            accum,
             location_vars: HashMap::new(),
             var_index: 1,
+            counts: None,
+            steps_left: None,
+            trap_arithmetic: false,
+            rsc_check: None,
         }
     }
 
@@ -79,12 +117,46 @@ impl<'a, M: Module> Program<'a, M> {
         func.def_var(self.accum, location_val);
     }
 
-    fn input(self: &mut Self, func: &mut FunctionBuilder, location: u32) {
-        let input_ref = self.rsc_input;
-        let loc = self.get_or_create_loc(func, location);
-        let input_inst = func.ins().call(input_ref, &[]);
-        let input_val = func.inst_results(input_inst)[0];
-        func.def_var(*loc, input_val);
+    fn input(self: &mut Self, func: &mut FunctionBuilder, location: u32, lineno: usize) {
+        let pointer_type = self.module.target_config().pointer_type();
+        let slot_addr = func.ins().stack_addr(pointer_type, self.input_slot, 0);
+        let line = func.ins().iconst(types::I32, lineno as i64);
+        let input_inst = func.ins().call(self.rsc_input, &[self.ctx, line, slot_addr]);
+        let status = func.inst_results(input_inst)[0];
+
+        self.stop_if(func, status, status, lineno);
+
+        let input_val = func.ins().stack_load(types::F64, self.input_slot, 0);
+        let loc = *self.get_or_create_loc(func, location);
+        func.def_var(loc, input_val);
+    }
+
+    // Sets the accumulator to the result of an arithmetic instruction, stopping the program if
+    // it's NaN and arithmetic traps.
+    fn arithmetic(self: &Self, func: &mut FunctionBuilder, result: Value, lineno: usize) {
+        func.def_var(self.accum, result);
+
+        if self.trap_arithmetic {
+            let is_nan = func.ins().fcmp(FloatCC::Unordered, result, result);
+            let status = func.ins().iconst(types::I32, NAN_STATUS as i64);
+            self.stop_if(func, is_nan, status, lineno);
+        }
+    }
+
+    // Stops the program with the given status if the condition is non-zero, after calling
+    // rsc_error to say why. Code emitted afterwards only runs if the program keeps going.
+    fn stop_if(self: &Self, func: &mut FunctionBuilder, condition: Value, status: Value, lineno: usize) {
+        let stop_block = func.create_block();
+        let continue_block = func.create_block();
+
+        func.ins().brif(condition, stop_block, &[], continue_block, &[]);
+
+        func.switch_to_block(stop_block);
+        let line = func.ins().iconst(types::I32, lineno as i64);
+        func.ins().call(self.rsc_error, &[self.ctx, status, line]);
+        func.ins().return_(&[status]);
+
+        func.switch_to_block(continue_block);
     }
 
     fn get_or_create_loc(self: &mut Self, func: &mut FunctionBuilder, location: u32) -> &Variable {
@@ -100,13 +172,141 @@ impl<'a, M: Module> Program<'a, M> {
         self.location_vars.get(&location).unwrap()
     }
 
+    fn limit_steps(self: &mut Self, func: &mut FunctionBuilder, max_steps: u64) {
+        let steps_left = Variable::new(self.var_index);
+        self.var_index += 1;
+
+        func.declare_var(steps_left, types::I64);
+        let max_steps = func.ins().iconst(types::I64, max_steps.min(i64::MAX as u64) as i64);
+        func.def_var(steps_left, max_steps);
+
+        self.steps_left = Some(steps_left);
+    }
+
+    // Takes the length of a basic block off the steps left, stopping the program if there aren't
+    // enough, when there's a step limit.
+    fn check_steps(self: &Self, func: &mut FunctionBuilder, len: usize, lineno: usize) {
+        if let Some(steps_left) = self.steps_left {
+            let left = func.use_var(steps_left);
+            let left = func.ins().iadd_imm(left, -(len as i64));
+            let exceeded = func.ins().icmp_imm(IntCC::SignedLessThan, left, 0);
+            let status = func.ins().iconst(types::I32, STEP_LIMIT_STATUS as i64);
+
+            self.stop_if(func, exceeded, status, lineno);
+            func.def_var(steps_left, left);
+        }
+    }
+
+    fn declare_check(self: &mut Self, func: &mut FunctionBuilder) {
+        let mut check_sig = runtime_signature(self.module);
+        check_sig.params.push(AbiParam::new(types::I32));
+        check_sig.returns.push(AbiParam::new(types::I32));
+
+        let check_func_id = self.module
+            .declare_function("rsc_check", Linkage::Import, &check_sig)
+            .unwrap();
+
+        self.rsc_check = Some(self.module.declare_func_in_func(check_func_id, func.func));
+    }
+
+    // Asks the runtime whether to keep going, returning the status it gives if not.
+    fn check_runtime(self: &Self, func: &mut FunctionBuilder, lineno: usize) {
+        if let Some(rsc_check) = self.rsc_check {
+            let line = func.ins().iconst(types::I32, lineno as i64);
+            let check_inst = func.ins().call(rsc_check, &[self.ctx, line]);
+            let status = func.inst_results(check_inst)[0];
+
+            let stop_block = func.create_block();
+            let continue_block = func.create_block();
+
+            func.ins().brif(status, stop_block, &[], continue_block, &[]);
+
+            func.switch_to_block(stop_block);
+            func.ins().return_(&[status]);
+
+            func.switch_to_block(continue_block);
+        }
+    }
+
+    // Adds one to the instruction's counter, when profiling.
+    fn count(self: &Self, func: &mut FunctionBuilder, idx: usize) {
+        if let Some(counts) = self.counts {
+            let offset = (idx * 8) as i32;
+            let count = func.ins().load(types::I64, MemFlags::trusted(), counts, offset);
+            let count = func.ins().iadd_imm(count, 1);
+            func.ins().store(MemFlags::trusted(), count, counts, offset);
+        }
+    }
+
     fn rand(self: &Self, func: &mut FunctionBuilder) -> Value {
-        let init_inst = func.ins().call(self.rsc_rand, &[]);
+        let init_inst = func.ins().call(self.rsc_rand, &[self.ctx]);
         func.inst_results(init_inst)[0]
     }
 }
 
+// The name of the compiled program's entry point. It takes a context pointer, which it passes to
+// the runtime functions (rsc_init, rsc_out, etc.) as their first argument, and returns the
+// program's exit status. The JIT passes a pointer to an emitter::RscRuntime, while rsc.c's main
+// passes NULL.
+pub const MAIN_NAME: &str = "rsc_main";
+
+// The statuses main returns when a program stops early, which become the exit status of rscc run
+// and of compiled executables. rsc.c defines the same ones.
+
+// The program ran out of steps (CompileOptions::max_steps).
+pub const STEP_LIMIT_STATUS: i32 = 3;
+
+// rsc_check stopped the program for running too long or printing too much.
+pub const TIMEOUT_STATUS: i32 = 4;
+pub const OUTPUT_LIMIT_STATUS: i32 = 5;
+
+// DIV's divisor was zero, or an arithmetic instruction's result was NaN, when arithmetic traps
+// (CompileOptions::trap_arithmetic).
+pub const DIVISION_BY_ZERO_STATUS: i32 = 6;
+pub const NAN_STATUS: i32 = 7;
+
+// INP hit the end of its input, or got too many invalid entries in a row.
+pub const END_OF_INPUT_STATUS: i32 = 8;
+pub const INVALID_INPUT_STATUS: i32 = 9;
+
+// INP couldn't read its input, or couldn't prompt for it or record it.
+pub const INPUT_ERROR_STATUS: i32 = 10;
+
+// Options for the code the compiler generates.
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    // Count how many times each instruction executes, in a data object named rsc_counts holding
+    // one 64-bit counter per instruction.
+    pub profile: bool,
+
+    // Stop programs that execute more than this many instructions. The count is checked at the
+    // start of each basic block, and a program stops before starting a block it can't finish.
+    // rsc_error is called with STEP_LIMIT_STATUS and the line number of the block.
+    pub max_steps: Option<u64>,
+
+    // Stop with DIVISION_BY_ZERO_STATUS before DIV divides by zero, and with NAN_STATUS after an
+    // arithmetic instruction (ADC, ADD, SUB, MUL or DIV) gives NaN. Otherwise arithmetic follows
+    // IEEE 754, so eg. 1 / 0 is inf and 0 / 0 is NaN.
+    pub trap_arithmetic: bool,
+
+    // Call rsc_check with the line number at the start of each basic block, and stop with the
+    // status it returns unless that's zero. This lets the runtime enforce limits the generated
+    // code doesn't know about, like timeouts, without stopping the process the program runs in.
+    pub checks: bool,
+}
+
+pub struct Compiled {
+    pub main_id: FuncId,
+
+    // The rsc_counts data object, when profiling.
+    pub counts_id: Option<DataId>,
+}
+
 pub fn compile<M: Module>(instructions: Vec<crate::parser::Instruction>, module: &mut M) -> FuncId {
+    compile_with_options(instructions, module, &CompileOptions::default()).main_id
+}
+
+pub fn compile_with_options<M: Module>(instructions: Vec<crate::parser::Instruction>, module: &mut M, options: &CompileOptions) -> Compiled {
     let mut builder_context = FunctionBuilderContext::new();
     let main_func = Function::new();
     let mut ctx = Context::for_function(main_func);
@@ -115,7 +315,15 @@ pub fn compile<M: Module>(instructions: Vec<crate::parser::Instruction>, module:
         &mut builder_context
     );
 
-    let mut program: Program<M> = Program::new(&mut main, module);
+    let counts_id = if options.profile {
+        Some(declare_counts(module, instructions.len()))
+    } else {
+        None
+    };
+
+    // main takes the runtime's context pointer and returns the program's exit status.
+    main.func.signature = runtime_signature(module);
+    main.func.signature.returns.push(AbiParam::new(types::I32));
 
     // Create the entry block, to start emitting code in.
     let entry_block = main.create_block();
@@ -129,19 +337,45 @@ pub fn compile<M: Module>(instructions: Vec<crate::parser::Instruction>, module:
     // Tell the builder to emit code in this block.
     main.switch_to_block(entry_block);
 
-    // And, tell the builder that this block will have no further
-    // predecessors. Since it's the entry block, it won't have any
-    // predecessors.
-    main.seal_block(entry_block);
+    let ctx_val = main.block_params(entry_block)[0];
+    let mut program: Program<M> = Program::new(&mut main, module, ctx_val);
+
+    // rsc_init goes first, since it seeds the random values
+    main.ins().call(program.rsc_init, &[ctx_val]);
 
     let rand_val = program.rand(&mut main);
 
     main.declare_var(program.accum, types::F64);
     main.def_var(program.accum, rand_val);
 
-    main.ins().call(program.rsc_init, &[]);
+    // Give every memory location its random starting value up front, so the entry block defines
+    // them all no matter which order the other blocks run in.
+    for instr in &instructions {
+        if let Some(location) = instr.location() {
+            program.get_or_create_loc(&mut main, location);
+        }
+    }
+
+    if let Some(counts_id) = counts_id {
+        let counts = program.module.declare_data_in_func(counts_id, main.func);
+        let pointer_type = program.module.target_config().pointer_type();
+        program.counts = Some(main.ins().global_value(pointer_type, counts));
+    }
+
+    if let Some(max_steps) = options.max_steps {
+        program.limit_steps(&mut main, max_steps);
+    }
+
+    program.trap_arithmetic = options.trap_arithmetic;
+
+    if options.checks {
+        program.declare_check(&mut main);
+    }
+
+    compile_blocks(&instructions, &mut program, &mut main);
 
-    compile_instructions(&instructions, 0, &mut program, &mut main);
+    // Every block's predecessors are known now that all of the branches have been emitted.
+    main.seal_all_blocks();
 
     // Tell the builder we're done with this function.
     main.finalize();
@@ -154,7 +388,7 @@ pub fn compile<M: Module>(instructions: Vec<crate::parser::Instruction>, module:
     // the function?
     let main_id = program
         .module
-        .declare_function("main", Linkage::Export, &ctx.func.signature)
+        .declare_function(MAIN_NAME, Linkage::Export, &ctx.func.signature)
         .map_err(|e| e.to_string())
         .unwrap();
 
@@ -166,202 +400,422 @@ pub fn compile<M: Module>(instructions: Vec<crate::parser::Instruction>, module:
     // Now that compilation is finished, we can clear out the context state.
     program.module.clear_context(&mut ctx);
 
-    main_id
+    Compiled { main_id, counts_id }
 }
 
-fn compile_instructions<M: Module>(instructions: &Vec<crate::parser::Instruction>, start_line: usize, program: &mut Program<M>, main: &mut FunctionBuilder) {
-    for instr in instructions {
-        if instr.lineno() < start_line {
-            continue
-        }
+// A signature for main or a runtime function, which all take the context pointer first.
+fn runtime_signature<M: Module>(module: &M) -> Signature {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(module.target_config().pointer_type()));
+    sig
+}
 
-        match instr {
-            // LoaD Accumulator
-            Instruction::LDA(lda) => {
-                program.load(main, lda.location);
-            },
+fn declare_counts<M: Module>(module: &mut M, len: usize) -> DataId {
+    let counts_id = module
+        .declare_data("rsc_counts", Linkage::Export, true, false)
+        .unwrap();
 
-            // LoaD Constant
-            Instruction::LDC(ldc) => {
-                let value = main.ins().f64const(ldc.value);
-                main.def_var(program.accum, value);
-            },
+    let mut description = DataDescription::new();
+    description.define_zeroinit(len.max(1) * 8);
+    description.set_align(8);
 
-            // STore Accumulator
-            Instruction::STA(sta) => {
-                program.store(main, sta.location);
-            },
+    module.define_data(counts_id, &description).unwrap();
+    counts_id
+}
 
-            // INPut
-            Instruction::INP(inp) => {
-                program.input(main, inp.location);
-            },
+// Emits a Cranelift block for each basic block of the program. Branches become jumps between
+// blocks, so loops in the program are loops in the generated code.
+fn compile_blocks<M: Module>(instructions: &[crate::parser::Instruction], program: &mut Program<M>, main: &mut FunctionBuilder) {
+    let blocks = cfg::basic_blocks(instructions);
+    let cl_blocks: Vec<Block> = blocks.iter().map(|_| main.create_block()).collect();
 
-            // OUTput
-            Instruction::OUT(out) => {
-                program.load(main, out.location);
-                let accum_val = main.use_var(program.accum);
-                main.ins().call(program.rsc_out, &[accum_val]);
-            },
+    // Branching past the last line or running off the end stops the program, like STP.
+    let exit_block = main.create_block();
 
-            // ADd Constant
-            Instruction::ADC(adc) => {
-                let value = main.ins().f64const(adc.value);
-                let accum_val = main.use_var(program.accum);
-                let new_accum = main.ins().fadd(accum_val, value);
-                main.def_var(program.accum, new_accum);
-            },
+    let block_at = |idx: Option<usize>| {
+        idx.and_then(|idx| cfg::block_containing(&blocks, idx))
+            .map(|block_idx| cl_blocks[block_idx])
+            .unwrap_or(exit_block)
+    };
 
-            // ADD
-            Instruction::ADD(add) => {
-                let location = program.get_or_create_loc(main, add.location);
-                let location_val = main.use_var(*location);
-                let accum_val = main.use_var(program.accum);
-                let new_accum = main.ins().fadd(accum_val, location_val);
-                main.def_var(program.accum, new_accum);
-            },
+    main.ins().jump(block_at(Some(0)), &[]);
 
-            // SUBtract
-            Instruction::SUB(sub) => {
-                let location = program.get_or_create_loc(main, sub.location);
-                let location_val = main.use_var(*location);
-                let accum_val = main.use_var(program.accum);
-                let new_accum = main.ins().fsub(accum_val, location_val);
-                main.def_var(program.accum, new_accum);
-            },
+    for (block_idx, block) in blocks.iter().enumerate() {
+        main.switch_to_block(cl_blocks[block_idx]);
+        program.check_steps(main, block.len(), instructions[block.start].lineno());
+        program.check_runtime(main, instructions[block.start].lineno());
 
-            // MULtiply
-            Instruction::MUL(mul) => {
-                let location = program.get_or_create_loc(main, mul.location);
-                let location_val = main.use_var(*location);
-                let accum_val = main.use_var(program.accum);
-                let new_accum = main.ins().fmul(accum_val, location_val);
-                main.def_var(program.accum, new_accum);
-            },
+        for (idx, instr) in instructions.iter().enumerate().take(block.end).skip(block.start) {
+            program.count(main, idx);
+            compile_instruction(instr, program, main);
+        }
 
-            // DIVide
-            Instruction::DIV(div) => {
-                let location = program.get_or_create_loc(main, div.location);
-                let location_val = main.use_var(*location);
-                let accum_val = main.use_var(program.accum);
-                let new_accum = main.ins().fdiv(accum_val, location_val);
-                main.def_var(program.accum, new_accum);
-            },
+        let next = block_at(Some(block.end));
+        let target = |location: u32| block_at(parser::target_index(instructions, location));
 
+        match &instructions[block.last()] {
             // BRanch Unconditional
             Instruction::BRU(bru) => {
-                let then_block = main.create_block();
-
-                main.ins().jump(then_block, &[]);
-                main.switch_to_block(then_block);
-                main.seal_block(then_block);
-
-                compile_instructions(instructions, bru.location as usize, program, main);
-
-                break;
+                main.ins().jump(target(bru.location), &[]);
             },
 
             // Branch Positive Accumulator
             Instruction::BPA(bpa) => {
-                compile_branch(
-                    FloatCC::GreaterThan,  // condition
-                    bpa.location,          // jump here if condition holds
-                    instructions,          // list of instructions
-                    program,
-                    main
-                );
+                compile_branch(FloatCC::GreaterThan, target(bpa.location), next, program, main);
             },
 
             // Branch Negative Accumulator
             Instruction::BNA(bna) => {
-                compile_branch(
-                    FloatCC::LessThan,     // condition
-                    bna.location,          // jump here if condition holds
-                    instructions,          // list of instructions
-                    program,
-                    main
-                );
+                compile_branch(FloatCC::LessThan, target(bna.location), next, program, main);
             },
 
             // Branch Zero Accumulator
             Instruction::BZA(bza) => {
-                compile_branch(
-                    FloatCC::Equal,        // condition
-                    bza.location,          // jump here if condition holds
-                    instructions,          // list of instructions
-                    program,
-                    main
-                );
+                compile_branch(FloatCC::Equal, target(bza.location), next, program, main);
             },
 
             // Stop
             Instruction::STP(_) => {
+                // a program that printed too much in its last block still has to report it
+                program.check_runtime(main, instructions[block.last()].lineno());
+
                 let izero = main.ins().iconst(types::I32, 0);
                 main.ins().return_(&[izero]);
-                break;
             },
+
+            _ => {
+                main.ins().jump(next, &[]);
+            }
         }
     }
+
+    main.switch_to_block(exit_block);
+    program.check_runtime(main, instructions.last().map_or(0, |instr| instr.lineno()));
+
+    let izero = main.ins().iconst(types::I32, 0);
+    main.ins().return_(&[izero]);
+}
+
+// Emits the code for an instruction that doesn't affect control flow. Branches and STP end their
+// basic block and are emitted by compile_blocks.
+fn compile_instruction<M: Module>(instr: &Instruction, program: &mut Program<M>, main: &mut FunctionBuilder) {
+    match instr {
+        // LoaD Accumulator
+        Instruction::LDA(lda) => {
+            program.load(main, lda.location);
+        },
+
+        // LoaD Constant
+        Instruction::LDC(ldc) => {
+            let value = main.ins().f64const(ldc.value);
+            main.def_var(program.accum, value);
+        },
+
+        // STore Accumulator
+        Instruction::STA(sta) => {
+            program.store(main, sta.location);
+        },
+
+        // INPut
+        Instruction::INP(inp) => {
+            program.input(main, inp.location, instr.lineno());
+        },
+
+        // OUTput
+        Instruction::OUT(out) => {
+            program.load(main, out.location);
+            let accum_val = main.use_var(program.accum);
+            main.ins().call(program.rsc_out, &[program.ctx, accum_val]);
+        },
+
+        // ADd Constant
+        Instruction::ADC(adc) => {
+            let value = main.ins().f64const(adc.value);
+            let accum_val = main.use_var(program.accum);
+            let new_accum = main.ins().fadd(accum_val, value);
+            program.arithmetic(main, new_accum, instr.lineno());
+        },
+
+        // ADD
+        Instruction::ADD(add) => {
+            let location = program.get_or_create_loc(main, add.location);
+            let location_val = main.use_var(*location);
+            let accum_val = main.use_var(program.accum);
+            let new_accum = main.ins().fadd(accum_val, location_val);
+            program.arithmetic(main, new_accum, instr.lineno());
+        },
+
+        // SUBtract
+        Instruction::SUB(sub) => {
+            let location = program.get_or_create_loc(main, sub.location);
+            let location_val = main.use_var(*location);
+            let accum_val = main.use_var(program.accum);
+            let new_accum = main.ins().fsub(accum_val, location_val);
+            program.arithmetic(main, new_accum, instr.lineno());
+        },
+
+        // MULtiply
+        Instruction::MUL(mul) => {
+            let location = program.get_or_create_loc(main, mul.location);
+            let location_val = main.use_var(*location);
+            let accum_val = main.use_var(program.accum);
+            let new_accum = main.ins().fmul(accum_val, location_val);
+            program.arithmetic(main, new_accum, instr.lineno());
+        },
+
+        // DIVide
+        Instruction::DIV(div) => {
+            let location = program.get_or_create_loc(main, div.location);
+            let location_val = main.use_var(*location);
+
+            if program.trap_arithmetic {
+                let fzero = main.ins().f64const(0.0);
+                let is_zero = main.ins().fcmp(FloatCC::Equal, location_val, fzero);
+                let status = main.ins().iconst(types::I32, DIVISION_BY_ZERO_STATUS as i64);
+                program.stop_if(main, is_zero, status, instr.lineno());
+            }
+
+            let accum_val = main.use_var(program.accum);
+            let new_accum = main.ins().fdiv(accum_val, location_val);
+            program.arithmetic(main, new_accum, instr.lineno());
+        },
+
+        Instruction::BRU(_) | Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_) | Instruction::STP(_) => {}
+    }
 }
 
-fn compile_branch<M: Module>(condition: FloatCC, then_location: u32, instructions: &Vec<crate::parser::Instruction>, program: &mut Program<M>, main: &mut FunctionBuilder) {
+fn compile_branch<M: Module>(condition: FloatCC, then_block: Block, else_block: Block, program: &mut Program<M>, main: &mut FunctionBuilder) {
     let accum_val = main.use_var(program.accum);
     let fzero = main.ins().f64const(0.0);
     let condition = main.ins().fcmp(condition, accum_val, fzero);
 
-    let then_block = main.create_block();
-    let else_block = main.create_block();
-    let merge_block = main.create_block();
-
     main.ins().brif(condition, then_block, &[], else_block, &[]);
-
-    main.switch_to_block(then_block);
-    main.seal_block(then_block);
-    compile_instructions(instructions, then_location as usize, program, main);
-
-    main.switch_to_block(else_block);
-    main.seal_block(else_block);
-    main.ins().jump(merge_block, &[]);
-
-    main.switch_to_block(merge_block);
-    main.seal_block(merge_block);
 }
 
 #[cfg(test)]
 mod tests {
-    use std::mem;
-    use std::cell::RefCell;
-
-    thread_local! {
-        static OUTPUTS: RefCell<Vec<f64>> = RefCell::new(vec![]);
+    use crate::emitter::{RscRuntime, RuntimeOptions, StdRuntime};
+    use crate::test_util::TempDir;
+
+    // Collects outputs instead of printing them, and feeds INP from a list.
+    struct TestRuntime {
+        outputs: Vec<f64>,
+        inputs: Vec<f64>,
+        starts: usize,
     }
 
-    fn rsc_out(value: f64) {
-        OUTPUTS.with_borrow_mut(|outputs| {
-            outputs.push((value * 100.0).round() / 100.0)
-        });
+    impl RscRuntime for TestRuntime {
+        fn output(self: &mut Self, value: f64) {
+            self.outputs.push((value * 100.0).round() / 100.0);
+        }
+
+        fn input(self: &mut Self) -> Result<f64, i32> {
+            match self.inputs.len() {
+                0 => Err(super::END_OF_INPUT_STATUS),
+                _ => Ok(self.inputs.remove(0))
+            }
+        }
+
+        fn random(self: &mut Self) -> f64 {
+            0.0
+        }
+
+        fn start(self: &mut Self) {
+            self.starts += 1;
+        }
     }
 
     fn run(program: &str) -> Vec<f64> {
-        OUTPUTS.with_borrow_mut(|outputs| outputs.clear());
-
         let result = crate::parser::parse(program);
         println!("{:?}", result.diagnostics);
         assert!(result.diagnostics.len() == 0);
 
-        let rsc_module = crate::emitter::emit_jit_module(
-            result.instructions,
-            Some(&|builder| {
-                builder.symbol("rsc_out", rsc_out as *const u8);
-            })
-        );
+        let rsc_module = crate::emitter::emit_jit_module(result.instructions);
+        let mut runtime = TestRuntime { outputs: vec![], inputs: vec![], starts: 0 };
+
+        assert!(rsc_module.run(&mut runtime) == 0);
+        assert!(runtime.starts == 1);
+        runtime.outputs
+    }
+
+    #[test]
+    fn each_run_uses_its_own_runtime() {
+        let result = crate::parser::parse("INP 10\nLDA 10\nADD 10\nSTA 10\nOUT 10\nSTP");
+        let rsc_module = crate::emitter::emit_jit_module(result.instructions);
+
+        let mut first = TestRuntime { outputs: vec![], inputs: vec![1.5], starts: 0 };
+        let mut second = TestRuntime { outputs: vec![], inputs: vec![4.0], starts: 0 };
+
+        rsc_module.run(&mut first);
+        rsc_module.run(&mut second);
+
+        assert!(first.outputs == vec![3.0]);
+        assert!(second.outputs == vec![8.0]);
+    }
+
+    #[test]
+    fn max_steps_stops_loops() {
+        let options = crate::compiler::CompileOptions { max_steps: Some(100), ..Default::default() };
+
+        let status = |program: &str| {
+            let result = crate::parser::parse(program);
+            let rsc_module = crate::emitter::emit_jit_module_with_options(result.instructions, &options);
+            rsc_module.run(&mut TestRuntime { outputs: vec![], inputs: vec![], starts: 0 })
+        };
+
+        assert!(status("LDC 1\nBPA 1\nSTP") == crate::compiler::STEP_LIMIT_STATUS);
+        assert!(status("LDC 1\nADC 1\nSTP") == 0);
+    }
+
+    #[test]
+    fn checks_stop_programs_that_print_too_much() {
+        let mut runtime = StdRuntime::new(&RuntimeOptions { max_output: Some(3), ..Default::default() }).unwrap();
 
-        let main = rsc_module.module.get_finalized_function(rsc_module.main_id);
-        let code_fn = unsafe { mem::transmute::<_, fn() -> ()>(main) };
+        let options = crate::compiler::CompileOptions { checks: true, ..Default::default() };
+        let result = crate::parser::parse("LDC 1\nSTA 10\nOUT 10\nBRU 3\nSTP");
+        let rsc_module = crate::emitter::emit_jit_module_with_options(result.instructions, &options);
 
-        code_fn();
+        assert!(rsc_module.run(&mut runtime) == crate::compiler::OUTPUT_LIMIT_STATUS);
+    }
+
+    #[test]
+    fn runtime_errors_stop_with_distinct_statuses() {
+        let status = |program: &str, trap_arithmetic: bool| {
+            let options = crate::compiler::CompileOptions { trap_arithmetic, ..Default::default() };
+            let result = crate::parser::parse(program);
+            let rsc_module = crate::emitter::emit_jit_module_with_options(result.instructions, &options);
+            rsc_module.run(&mut TestRuntime { outputs: vec![], inputs: vec![], starts: 0 })
+        };
+
+        let divide_by_zero = "LDC 0\nSTA 10\nLDC 1\nDIV 10\nSTP";
+        let nan = "LDC 1e200\nSTA 10\nMUL 10\nSTA 11\nSUB 11\nSTP";
+
+        assert!(status(divide_by_zero, false) == 0);
+        assert!(status(divide_by_zero, true) == crate::compiler::DIVISION_BY_ZERO_STATUS);
+        assert!(status(nan, false) == 0);
+        assert!(status(nan, true) == crate::compiler::NAN_STATUS);
+        assert!(status("INP 10\nSTP", false) == crate::compiler::END_OF_INPUT_STATUS);
+    }
+
+    #[test]
+    fn scripted_inputs_are_recorded() {
+        let dir = TempDir::new("record");
+        let path = dir.path().join("record.txt");
+
+        let mut runtime = StdRuntime::new(&RuntimeOptions {
+            inputs: vec![2.0, -3.5, 0.1],
+            no_prompt: true,
+            record: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        }).unwrap();
+
+        let result = crate::parser::parse("INP 10\nINP 11\nINP 12\nSTP");
+        let rsc_module = crate::emitter::emit_jit_module(result.instructions);
+
+        assert!(rsc_module.run(&mut runtime) == 0);
+        drop(runtime);
+
+        let recorded = std::fs::read_to_string(&path).unwrap();
+
+        assert!(recorded == "2\n-3.5\n0.1\n");
+    }
+
+    #[test]
+    fn recorded_inputs_are_formatted_like_rsc_c() {
+        // what rsc.c's loop over %.*g writes for each value
+        let cases = [
+            (3.0, "3"), (-3.5, "-3.5"), (0.1, "0.1"), (1.0 / 3.0, "0.3333333333333333"), (100.0, "100"),
+            (123456.0, "123456"), (0.0001, "0.0001"), (0.00001234, "1.234e-05"), (1e16, "1e+16"),
+            (123456789012345678.0, "1.2345678901234568e+17"), (2.5e-300, "2.5e-300"), (-0.0, "-0"),
+        ];
+
+        for (value, expected) in cases {
+            assert!(crate::emitter::format_input(value) == expected, "{} is written as {}", value, crate::emitter::format_input(value));
+        }
+    }
+
+    #[test]
+    fn typed_inputs_are_read_like_rsc_c() {
+        // what's typed for INP 10 / OUT 10, and the value or status read_input gives back
+        let cases = [
+            ("3abc\n\n4\n", Ok(4.0)),
+            ("1 2\n", Err(super::END_OF_INPUT_STATUS)),
+            (" -2.5 \r\n", Ok(-2.5)),
+            ("a\n\n0x10\ninf\n1e\n2\n", Err(super::INVALID_INPUT_STATUS)),
+        ];
+
+        let dir = TempDir::new("typed");
+        let object = dir.path().join("typed.o");
+        let executable = dir.path().join("typed");
+
+        let result = crate::parser::parse("INP 10\nOUT 10\nSTP");
+        let rsc_module = crate::emitter::emit_object_module(target_lexicon::Triple::host(), result.instructions);
+        std::fs::write(&object, rsc_module.product.emit().unwrap()).unwrap();
+
+        let linked = std::process::Command::new("cc")
+            .arg(&object)
+            .arg(std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("rsc.c"))
+            .arg("-lm")
+            .arg("-o")
+            .arg(&executable)
+            .status()
+            .unwrap();
+
+        assert!(linked.success());
+
+        for (typed, expected) in cases {
+            let mut printed = vec![];
+            let read = crate::emitter::read_input(&mut typed.as_bytes(), &mut printed, false);
+            assert!(read == expected, "{:?} is read as {:?}", typed, read);
+
+            let mut child = std::process::Command::new(&executable)
+                .arg("--no-prompt")
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .unwrap();
+
+            std::io::Write::write_all(&mut child.stdin.take().unwrap(), typed.as_bytes()).unwrap();
+            let compiled = child.wait_with_output().unwrap();
+
+            let mut printed = String::from_utf8(printed).unwrap();
+            if let Ok(value) = read {
+                printed += &format!("{:.2}\n", value);
+            }
+
+            assert!(String::from_utf8(compiled.stdout).unwrap() == printed, "{:?} prints differently", typed);
+            assert!(compiled.status.code() == Some(read.err().unwrap_or(0)), "{:?} stops differently", typed);
+        }
+    }
+
+    // Fails every read and write, like a closed terminal.
+    struct Broken;
+
+    impl std::io::Read for Broken {
+        fn read(self: &mut Self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
+    }
+
+    impl std::io::Write for Broken {
+        fn write(self: &mut Self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken"))
+        }
+
+        fn flush(self: &mut Self) -> std::io::Result<()> {
+            Err(std::io::Error::other("broken"))
+        }
+    }
+
+    #[test]
+    fn input_errors_stop_the_program() {
+        let mut printed = vec![];
+        let read = crate::emitter::read_input(&mut std::io::BufReader::new(Broken), &mut printed, false);
+        assert!(read == Err(super::INPUT_ERROR_STATUS));
 
-        OUTPUTS.with_borrow(|v| v.clone())
+        let read = crate::emitter::read_input(&mut "3\n".as_bytes(), &mut Broken, true);
+        assert!(read == Err(super::INPUT_ERROR_STATUS));
     }
 
     #[test]
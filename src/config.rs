@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::emitter::RuntimeOptions;
+use crate::lint::{Level, LintLevels};
+use crate::parser::ParseOptions;
+
+pub const CONFIG_FILE_NAME: &str = "rscc.toml";
+
+// Project-wide settings, read from an rscc.toml file. A course can check one of these into the
+// directory containing its exercises instead of passing the same options on every command line.
+// For example:
+//
+// memory-size = 100
+// output-path = "out"
+//
+// [lints]
+// warn = ["pedagogy"]
+// deny = ["output-uncomputed"]
+//
+// [dialect]
+// inline-comments = true
+//
+// [runtime]
+// seed = 42
+// precision = 3
+//
+// [names]
+// 10 = "count"
+//
+// [labels]
+// loop = 5
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub lints: LintConfig,
+    pub dialect: DialectConfig,
+    pub memory_size: Option<u32>,
+    pub output_path: Option<String>,
+    pub runtime: RuntimeConfig,
+
+    // Names for memory locations, used when printing programs as pseudocode.
+    pub names: BTreeMap<String, String>,
+
+    // Names for lines, so the debugger can eg. break at "loop" instead of a line number.
+    pub labels: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LintConfig {
+    pub allow: Vec<String>,
+    pub warn: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DialectConfig {
+    pub inline_comments: bool,
+    pub case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    // Seeds the random values uninitialized memory locations start out with, making runs
+    // repeatable.
+    pub seed: Option<u64>,
+
+    // The number of decimal places OUT prints.
+    pub precision: Option<usize>,
+}
+
+impl Config {
+    pub fn parse(contents: &str) -> Result<Config, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    // Loads the given config file. Relative paths inside it are relative to the directory the file
+    // lives in, not the current working directory.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                let mut config = Config::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+                if let (Some(output_path), Some(dir)) = (&config.output_path, path.parent()) {
+                    config.output_path = Some(dir.join(output_path).to_string_lossy().to_string());
+                }
+
+                Ok(config)
+            }
+
+            Err(e) => Err(format!("{}: {}", path.display(), e))
+        }
+    }
+
+    // Looks for an rscc.toml file in the directory containing the given RSC file, then in each of
+    // its parent directories in turn. Returns the default configuration if none is found.
+    pub fn discover(file: &Path) -> Result<Config, String> {
+        match find(file) {
+            Some(path) => Config::load(&path),
+            None => Ok(Config::default())
+        }
+    }
+
+    pub fn parse_options(self: &Self) -> ParseOptions {
+        ParseOptions {
+            inline_comments: self.dialect.inline_comments,
+            case_insensitive: self.dialect.case_insensitive,
+            memory_size: self.memory_size,
+        }
+    }
+
+    pub fn runtime_options(self: &Self) -> RuntimeOptions {
+        RuntimeOptions {
+            seed: self.runtime.seed,
+            precision: self.runtime.precision,
+            ..Default::default()
+        }
+    }
+
+    pub fn names(self: &Self) -> Result<HashMap<u32, String>, String> {
+        self.names.iter().map(|(location, name)| {
+            match location.parse::<u32>() {
+                Ok(location) => Ok((location, name.clone())),
+                Err(_) => Err(format!("'{}' is not a memory location", location))
+            }
+        }).collect()
+    }
+
+    pub fn lint_levels(self: &Self) -> Result<LintLevels, String> {
+        let mut levels = LintLevels::default();
+
+        levels.set_all(&self.lints.allow, Level::Allow)?;
+        levels.set_all(&self.lints.warn, Level::Warn)?;
+        levels.set_all(&self.lints.deny, Level::Deny)?;
+
+        Ok(levels)
+    }
+}
+
+pub fn find(file: &Path) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(file).ok()?;
+    let mut dir = absolute.parent();
+
+    while let Some(current) = dir {
+        let candidate = current.join(CONFIG_FILE_NAME);
+
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+
+        dir = current.parent();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::Lint;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn it_parses_a_full_config() {
+        let config = Config::parse(r#"
+            memory-size = 100
+            output-path = "out"
+
+            [lints]
+            warn = ["pedagogy"]
+            deny = ["output-uncomputed"]
+
+            [dialect]
+            inline-comments = true
+
+            [runtime]
+            seed = 42
+            precision = 3
+
+            [names]
+            10 = "count"
+
+            [labels]
+            loop = 5
+        "#).unwrap();
+
+        assert!(config.memory_size == Some(100));
+        assert!(config.output_path == Some("out".to_string()));
+        assert!(config.dialect.inline_comments);
+        assert!(!config.dialect.case_insensitive);
+        assert!(config.runtime.seed == Some(42));
+        assert!(config.runtime.precision == Some(3));
+        assert!(config.names().unwrap() == HashMap::from([(10, "count".to_string())]));
+        assert!(config.labels == BTreeMap::from([("loop".to_string(), 5)]));
+
+        let levels = config.lint_levels().unwrap();
+        assert!(levels.level(Lint::LdcOfLocation) == Level::Warn);
+        assert!(levels.level(Lint::OutputUncomputed) == Level::Deny);
+    }
+
+    #[test]
+    fn it_defaults_missing_settings() {
+        let config = Config::parse("").unwrap();
+
+        assert!(config.memory_size.is_none());
+        assert!(config.lint_levels().unwrap().enabled().len() == 0);
+    }
+
+    #[test]
+    fn it_rejects_unknown_settings() {
+        assert!(Config::parse("memory = 100").is_err());
+        assert!(Config::parse("[lints]\nwarn = [\"foo\"]").unwrap().lint_levels().is_err());
+        assert!(Config::parse("[names]\nfoo = \"bar\"").unwrap().names().is_err());
+    }
+
+    #[test]
+    fn it_discovers_config_files_in_parent_directories() {
+        let root = TempDir::new("config");
+        root.write(CONFIG_FILE_NAME, "memory-size = 50");
+        let program = root.write("week1/exercises/prog.rsc", "STP");
+
+        let config = Config::discover(&program).unwrap();
+        let canonical_root = fs::canonicalize(root.path()).unwrap();
+        drop(root);
+
+        assert!(config.memory_size == Some(50));
+        assert!(config.output_path.is_none());
+        assert!(find(&canonical_root.join("missing.rsc")).is_none());
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeSet;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use crate::cfg;
+use crate::interpreter::{self, Event, Machine};
+use crate::parser::Instruction;
+
+// Searches for inputs that exercise every branch of a program, so that instructors know which
+// inputs to grade a submission with. The search is random, but guided: values are drawn from the
+// constants the program uses and from inputs that already reached new branches.
+
+pub struct SearchOptions {
+    // The number of input vectors to try before giving up on the remaining branches.
+    pub trials: usize,
+    pub seed: Option<u64>,
+
+    // Runs that execute more instructions than this are abandoned, so that inputs which send the
+    // program into an infinite loop aren't reported.
+    pub max_steps: u64,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions { trials: 1000, seed: None, max_steps: 10_000 }
+    }
+}
+
+// One way a conditional branch can go: the index of the BPA, BNA or BZA and whether it jumps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Direction {
+    pub idx: usize,
+    pub taken: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestVector {
+    pub inputs: Vec<f64>,
+
+    // The directions this vector was the first to cover.
+    pub covers: Vec<Direction>,
+}
+
+#[derive(Debug)]
+pub struct Coverage {
+    pub vectors: Vec<TestVector>,
+    pub uncovered: Vec<Direction>,
+    pub total: usize,
+
+    // The number of trials abandoned at the step limit.
+    pub timeouts: usize,
+}
+
+// Runs the program once, reading inputs from the start of the vector and drawing more with
+// next_value once it runs out. Returns the directions taken, or None if the step limit was hit.
+// The vector is truncated to the inputs that were actually read.
+fn run_trial(instructions: &[Instruction], inputs: &mut Vec<f64>, seed: u64, max_steps: u64, mut next_value: impl FnMut() -> f64) -> Option<BTreeSet<Direction>> {
+    let mut machine = Machine::new(instructions, Some(seed));
+    let mut directions = BTreeSet::new();
+    let mut consumed = 0;
+
+    while machine.steps < max_steps {
+        let before = machine.pc;
+
+        if let (Some(idx), Some(instr)) = (before, machine.current()) {
+            if matches!(instr, Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_)) {
+                directions.insert(Direction { idx, taken: interpreter::branch_taken(instr, machine.accum) });
+            }
+        }
+
+        match machine.step() {
+            Event::InputRequest(_) => {
+                if consumed == inputs.len() {
+                    inputs.push(next_value());
+                }
+
+                machine.input(inputs[consumed]);
+                consumed += 1;
+            }
+
+            Event::Halted => {
+                inputs.truncate(consumed);
+                return Some(directions);
+            }
+
+            Event::Executed | Event::Output(_) => {}
+        }
+    }
+
+    None
+}
+
+// The values most likely to steer a branch: small numbers, and the constants the program
+// compares against.
+pub fn interesting_values(instructions: &[Instruction]) -> Vec<f64> {
+    let mut values = vec![0.0, 1.0, -1.0, 2.0, -2.0, 10.0, -10.0];
+
+    for instr in instructions {
+        let constant = match instr {
+            Instruction::LDC(ldc) => ldc.value,
+            Instruction::ADC(adc) => adc.value,
+            _ => continue
+        };
+
+        for value in [constant, -constant, constant + 1.0, constant - 1.0, -constant + 1.0, -constant - 1.0] {
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+    }
+
+    values
+}
+
+pub fn generate(instructions: &[Instruction], options: &SearchOptions) -> Coverage {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy()
+    };
+
+    // only branches that can actually run need covering
+    let blocks = cfg::basic_blocks(instructions);
+    let mut remaining = BTreeSet::new();
+
+    for block_idx in cfg::reachable(&blocks) {
+        let idx = blocks[block_idx].last();
+
+        if matches!(instructions[idx], Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_)) {
+            remaining.insert(Direction { idx, taken: true });
+            remaining.insert(Direction { idx, taken: false });
+        }
+    }
+
+    let total = remaining.len();
+    let pool = interesting_values(instructions);
+    let mut vectors: Vec<TestVector> = vec![];
+    let mut timeouts = 0;
+
+    for _ in 0..options.trials {
+        if remaining.len() == 0 && vectors.len() > 0 {
+            break;
+        }
+
+        // either tweak one input of a vector that already found something, or start afresh
+        let mut inputs = match vectors.choose(&mut rng) {
+            Some(vector) if rng.gen_bool(0.5) => {
+                let mut inputs = vector.inputs.clone();
+
+                if inputs.len() > 0 {
+                    let position = rng.gen_range(0..inputs.len());
+                    inputs[position] = pick_value(&mut rng, &pool);
+                    inputs.truncate(position + 1);
+                }
+
+                inputs
+            }
+
+            _ => vec![]
+        };
+
+        let seed = rng.gen();
+        let directions = run_trial(instructions, &mut inputs, seed, options.max_steps, || pick_value(&mut rng, &pool));
+
+        let directions = match directions {
+            Some(directions) => directions,
+            None => {
+                timeouts += 1;
+                continue;
+            }
+        };
+
+        let covers: Vec<Direction> = directions.intersection(&remaining).copied().collect();
+
+        // the first run that finishes is kept even if there are no branches, so there's always
+        // at least one vector to test with
+        if covers.len() > 0 || vectors.len() == 0 {
+            for direction in &covers {
+                remaining.remove(direction);
+            }
+
+            vectors.push(TestVector { inputs, covers });
+        }
+    }
+
+    Coverage {
+        vectors,
+        uncovered: remaining.into_iter().collect(),
+        total,
+        timeouts,
+    }
+}
+
+// Draws an input, either from the given pool of interesting values or a random integer.
+pub fn pick_value(rng: &mut StdRng, pool: &[f64]) -> f64 {
+    if rng.gen_bool(0.6) {
+        *pool.choose(rng).unwrap()
+    } else {
+        rng.gen_range(-100..=100) as f64
+    }
+}
+
+fn describe(instructions: &[Instruction], direction: &Direction) -> String {
+    let instr = &instructions[direction.idx];
+    format!("line {} ({})", instr.lineno(), cfg::branch_condition(instr, !direction.taken).unwrap())
+}
+
+// Prints the vectors one per line with their inputs separated by commas, which parse_vectors reads
+// back. Comments starting with # say which branches each vector covers.
+pub fn render(instructions: &[Instruction], coverage: &Coverage) -> String {
+    let mut lines = vec![format!(
+        "# {} of {} branch directions covered by {} input vectors",
+        coverage.total - coverage.uncovered.len(),
+        coverage.total,
+        coverage.vectors.len()
+    )];
+
+    for vector in &coverage.vectors {
+        if vector.covers.len() > 0 {
+            let covers: Vec<String> = vector.covers.iter().map(|d| describe(instructions, d)).collect();
+            lines.push(format!("# {}", covers.join(", ")));
+        }
+
+        let inputs: Vec<String> = vector.inputs.iter().map(|value| value.to_string()).collect();
+        lines.push(inputs.join(","));
+    }
+
+    for direction in &coverage.uncovered {
+        lines.push(format!("# not covered: {}", describe(instructions, direction)));
+    }
+
+    if coverage.timeouts > 0 {
+        lines.push(format!("# {} trials were abandoned at the step limit", coverage.timeouts));
+    }
+
+    lines.join("\n")
+}
+
+// Reads input vectors in the format written by render. Blank lines and comments are ignored.
+pub fn parse_vectors(text: &str) -> Result<Vec<Vec<f64>>, String> {
+    let mut vectors = vec![];
+
+    for (lineno, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+
+        if line.len() == 0 {
+            continue;
+        }
+
+        let vector: Result<Vec<f64>, String> = line.split(',').map(|value| {
+            value.trim().parse::<f64>().map_err(|_| format!("line {}: invalid input value '{}'", lineno + 1, value.trim()))
+        }).collect();
+
+        vectors.push(vector?);
+    }
+
+    Ok(vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn options() -> SearchOptions {
+        SearchOptions { seed: Some(1), ..SearchOptions::default() }
+    }
+
+    #[test]
+    fn it_covers_every_branch_direction() {
+        // prints 1, 2 or 3 depending on whether the input is negative, zero or positive
+        let result = parser::parse(
+            "INP 10\nLDA 10\nBNA 8\nBZA 11\nLDC 3\nSTA 11\nBRU 13\nLDC 1\nSTA 11\nBRU 13\nLDC 2\nSTA 11\nOUT 11\nSTP"
+        );
+
+        let coverage = generate(&result.instructions, &options());
+
+        assert!(coverage.total == 4);
+        assert!(coverage.uncovered.len() == 0);
+        assert!(coverage.vectors.iter().any(|v| v.inputs == vec![0.0]));
+        assert!(coverage.vectors.iter().all(|v| v.inputs.len() == 1));
+    }
+
+    #[test]
+    fn it_abandons_runs_at_the_step_limit() {
+        // loops forever unless the input is 42, so the BZA can never finish by falling through
+        let result = parser::parse("INP 10\nLDA 10\nADC -42\nBZA 6\nBRU 2\nSTP");
+        let coverage = generate(&result.instructions, &SearchOptions { trials: 100, ..options() });
+
+        assert!(coverage.uncovered == vec![Direction { idx: 3, taken: false }]);
+        assert!(coverage.timeouts > 0);
+        assert!(coverage.vectors.iter().all(|v| v.inputs == vec![42.0]));
+    }
+
+    #[test]
+    fn it_round_trips_vectors() {
+        let result = parser::parse("INP 10\nINP 11\nLDA 10\nSUB 11\nBPA 7\nOUT 10\nSTP");
+        let coverage = generate(&result.instructions, &options());
+        let vectors = parse_vectors(&render(&result.instructions, &coverage)).unwrap();
+
+        assert!(vectors.len() == coverage.vectors.len());
+        assert!(vectors.iter().zip(&coverage.vectors).all(|(parsed, vector)| *parsed == vector.inputs));
+    }
+
+    #[test]
+    fn it_rejects_invalid_vectors() {
+        assert!(parse_vectors("1,2\n# comment\n\n3, -4.5 # trailing\n") == Ok(vec![vec![1.0, 2.0], vec![3.0, -4.5]]));
+        assert!(parse_vectors("1,x").is_err());
+    }
+}
@@ -0,0 +1,656 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use serde_json::{json, Value};
+use crate::config::Config;
+use crate::debugger::{Session, Stop, Target};
+use crate::emitter::DEFAULT_PRECISION;
+use crate::parser::{self, Instruction};
+
+// A Debug Adapter Protocol server, so editors like VS Code can debug RSC programs graphically.
+// Messages are read from and written to the given streams, normally stdin and stdout, each with a
+// Content-Length header. The debugging itself is done by a debugger Session: the server translates
+// requests into session calls and stops into events.
+//
+// Requests are read on a separate thread, so that a program that has been continued runs in chunks
+// of CHUNK_STEPS instructions with a look for new requests in between, and can be paused.
+//
+// There's a single thread and a single stack frame. The variables view shows the accumulator and
+// every memory location that has been used. Programs get their input from the debug console, where
+// typing numbers queues them for upcoming INP instructions and anything else is run as an rscdb
+// command, eg. "watch m[10]" or "last-write 12".
+
+const THREAD_ID: u64 = 1;
+const FRAME_ID: u64 = 1;
+const MACHINE_SCOPE: u64 = 1;
+const CHUNK_STEPS: u64 = 10_000;
+
+// How to carry on running, remembered so that an INP that stopped the program for input can pick
+// up where it left off once the input arrives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Step,
+    Over,
+    Continue,
+}
+
+// Reads the next message, or None at the end of the input.
+fn read_message(input: &mut dyn BufRead) -> Result<Option<Value>, String> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+
+        if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+
+        if line.len() == 0 {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| format!("Invalid header '{}'", line))?);
+        }
+    }
+
+    let length = length.ok_or("Message without a Content-Length header")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+
+    serde_json::from_slice(&body).map(Some).map_err(|e| e.to_string())
+}
+
+struct Connection<'a> {
+    // Messages from the reader thread, which stops after the end of the input or an error.
+    requests: Receiver<Result<Option<Value>, String>>,
+    pending: Option<Result<Option<Value>, String>>,
+
+    output: &'a mut dyn Write,
+    seq: u64,
+}
+
+impl<'a> Connection<'a> {
+    // Waits for the next message, or None at the end of the input.
+    fn receive(self: &mut Self) -> Result<Option<Value>, String> {
+        self.pending.take().or_else(|| self.requests.recv().ok()).unwrap_or(Ok(None))
+    }
+
+    // Whether receive has something to return straight away.
+    fn has_request(self: &mut Self) -> bool {
+        if self.pending.is_none() {
+            match self.requests.try_recv() {
+                Ok(message) => self.pending = Some(message),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => {}
+            }
+        }
+
+        true
+    }
+
+    fn send(self: &mut Self, mut message: Value) -> Result<(), String> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body).map_err(|e| e.to_string())?;
+        self.output.flush().map_err(|e| e.to_string())
+    }
+
+    fn respond(self: &mut Self, request: &Value, body: Value) -> Result<(), String> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(self: &mut Self, request: &Value, message: &str) -> Result<(), String> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(self: &mut Self, event: &str, body: Value) -> Result<(), String> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+// A program loaded by a launch request, along with its settings from rscc.toml.
+struct Program {
+    path: String,
+    source: String,
+    instructions: Vec<Instruction>,
+    config: Config,
+    names: HashMap<u32, String>,
+}
+
+fn load(path: &str) -> Result<Program, String> {
+    let config = Config::discover(Path::new(path))?;
+    let names = config.names()?;
+    let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let result = parser::parse_with_options(&source, &config.parse_options());
+
+    if result.diagnostics.len() > 0 {
+        let mut message = format!("Found {} compilation problem(s)\n", result.diagnostics.len());
+
+        for diagnostic in &result.diagnostics {
+            message += &format!("\n{}\n", diagnostic.annotate(&source));
+        }
+
+        return Err(message);
+    }
+
+    Ok(Program { path: path.to_string(), source, instructions: result.instructions, config, names })
+}
+
+// Serves a single debugging session: an initialize request, then a launch, then whatever the
+// editor asks for until it disconnects or the input ends.
+pub fn serve<R: BufRead + Send + 'static>(input: R, output: &mut dyn Write) -> Result<(), String> {
+    let (sender, requests) = mpsc::channel();
+
+    // the thread is left blocked on the input if the editor disconnects without closing it
+    thread::spawn(move || {
+        let mut input = input;
+
+        loop {
+            let message = read_message(&mut input);
+            let last = !matches!(message, Ok(Some(_)));
+
+            if sender.send(message).is_err() || last {
+                break;
+            }
+        }
+    });
+
+    let mut connection = Connection { requests, pending: None, output, seq: 0 };
+
+    while let Some(request) = connection.receive()? {
+        match request["command"].as_str().unwrap_or("") {
+            "initialize" => connection.respond(&request, json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsStepBack": true,
+                "supportsEvaluateForHovers": true,
+            }))?,
+
+            "launch" => {
+                let path = match request["arguments"]["program"].as_str() {
+                    Some(path) => path,
+                    None => {
+                        connection.fail(&request, "launch needs the path of a program")?;
+                        continue;
+                    }
+                };
+
+                match load(path) {
+                    Ok(program) => {
+                        let stop_on_entry = request["arguments"]["stopOnEntry"].as_bool().unwrap_or(false);
+                        connection.respond(&request, json!({}))?;
+                        connection.event("initialized", json!({}))?;
+                        return debug(&mut connection, &program, stop_on_entry);
+                    }
+
+                    Err(message) => {
+                        connection.event("output", json!({ "category": "stderr", "output": format!("{}\n", message) }))?;
+                        connection.fail(&request, message.lines().next().unwrap_or(""))?;
+                    }
+                }
+            }
+
+            "disconnect" => return connection.respond(&request, json!({})),
+            _ => connection.fail(&request, "Launch a program first")?
+        }
+    }
+
+    Ok(())
+}
+
+fn debug(connection: &mut Connection, program: &Program, stop_on_entry: bool) -> Result<(), String> {
+    let runtime_options = program.config.runtime_options();
+    let precision = runtime_options.precision.unwrap_or(DEFAULT_PRECISION);
+
+    let session = Session::new(&program.source, &program.instructions, runtime_options.seed, precision)
+        .with_names(program.names.clone(), program.config.labels.clone());
+
+    let mut adapter = Adapter { session, path: &program.path, precision, stop_on_entry, waiting: None, running: false };
+
+    loop {
+        // a continued program runs whenever there are no requests to answer
+        if adapter.running && !connection.has_request() {
+            adapter.run_chunk(connection)?;
+            continue;
+        }
+
+        match connection.receive()? {
+            Some(request) if adapter.handle(connection, &request)? => {}
+            _ => break
+        }
+    }
+
+    Ok(())
+}
+
+struct Adapter<'a> {
+    session: Session<'a>,
+    path: &'a str,
+    precision: usize,
+    stop_on_entry: bool,
+
+    // Set when the program stopped at an INP with no input queued.
+    waiting: Option<Mode>,
+
+    // Set while a continued program is running in chunks between requests.
+    running: bool,
+}
+
+impl<'a> Adapter<'a> {
+    // Handles a request. Returns false once the editor has disconnected.
+    fn handle(self: &mut Self, connection: &mut Connection, request: &Value) -> Result<bool, String> {
+        let arguments = &request["arguments"];
+
+        match request["command"].as_str().unwrap_or("") {
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                connection.respond(request, body)?;
+            }
+
+            "configurationDone" => {
+                connection.respond(request, json!({}))?;
+
+                if self.stop_on_entry {
+                    self.stopped(connection, "entry", None)?;
+                } else {
+                    self.run(connection, Mode::Continue)?;
+                }
+            }
+
+            "threads" => connection.respond(request, json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }))?,
+            "stackTrace" => connection.respond(request, self.stack_trace())?,
+
+            "scopes" => connection.respond(request, json!({
+                "scopes": [{ "name": "Machine", "variablesReference": MACHINE_SCOPE, "expensive": false }]
+            }))?,
+
+            "variables" => connection.respond(request, self.variables())?,
+
+            // Step In and Step Over both execute one instruction, since there are no calls to step
+            // into. Step Out runs until the instruction after the current one, leaving a loop.
+            "next" | "stepIn" => self.run_after(connection, request, Mode::Step)?,
+            "stepOut" => self.run_after(connection, request, Mode::Over)?,
+            "continue" => self.run_after(connection, request, Mode::Continue)?,
+
+            "stepBack" | "reverseContinue" => {
+                connection.respond(request, json!({}))?;
+                self.waiting = None;
+                self.running = false;
+
+                let stop = match request["command"].as_str() {
+                    Some("stepBack") => self.session.reverse_step(),
+                    _ => self.session.reverse_resume()
+                };
+
+                self.report(connection, stop, Mode::Step)?;
+            }
+
+            "evaluate" => self.evaluate(connection, request)?,
+
+            "pause" => {
+                connection.respond(request, json!({}))?;
+
+                if self.running {
+                    self.running = false;
+                    self.stopped(connection, "pause", None)?;
+                }
+            }
+
+            "setExceptionBreakpoints" => connection.respond(request, json!({}))?,
+
+            "disconnect" | "terminate" => {
+                connection.respond(request, json!({}))?;
+                return Ok(false);
+            }
+
+            command => connection.fail(request, &format!("Unsupported request '{}'", command))?
+        }
+
+        Ok(true)
+    }
+
+    fn set_breakpoints(self: &mut Self, arguments: &Value) -> Value {
+        let instructions = self.session.machine.instructions();
+        let requested = arguments["breakpoints"].as_array().cloned().unwrap_or_default();
+        self.session.breakpoints.clear();
+
+        let breakpoints: Vec<Value> = requested.iter().map(|breakpoint| {
+            let line = breakpoint["line"].as_u64().unwrap_or(0);
+
+            // breakpoints on blank lines and comments move to the next instruction, like rscdb
+            match self.session.resolve_breakpoint(&line.to_string()) {
+                Ok(idx) => {
+                    self.session.breakpoints.insert(idx);
+                    json!({ "verified": true, "line": instructions[idx].lineno() })
+                }
+
+                Err(message) => json!({ "verified": false, "line": line, "message": message })
+            }
+        }).collect();
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(self: &Self) -> Value {
+        let frames = match self.session.machine.current() {
+            Some(instr) => {
+                let name = Path::new(self.path).file_name().map(|name| name.to_string_lossy().to_string());
+
+                vec![json!({
+                    "id": FRAME_ID,
+                    "name": "main",
+                    "line": instr.lineno(),
+                    "column": 1,
+                    "source": { "name": name, "path": self.path },
+                })]
+            }
+
+            None => vec![]
+        };
+
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(self: &Self) -> Value {
+        let machine = &self.session.machine;
+        let mut locations: Vec<&u32> = machine.memory.keys().collect();
+        locations.sort();
+
+        let mut variables = vec![json!({ "name": "acc", "value": machine.accum.to_string(), "variablesReference": 0 })];
+
+        for location in locations {
+            variables.push(json!({
+                "name": self.session.location_name(*location),
+                "value": machine.memory[location].to_string(),
+                "variablesReference": 0,
+            }));
+        }
+
+        json!({ "variables": variables })
+    }
+
+    fn run_after(self: &mut Self, connection: &mut Connection, request: &Value, mode: Mode) -> Result<(), String> {
+        connection.respond(request, json!({ "allThreadsContinued": true }))?;
+        self.run(connection, mode)
+    }
+
+    // Continuing runs the first chunk straight away and leaves the rest to the debug loop.
+    fn run(self: &mut Self, connection: &mut Connection, mode: Mode) -> Result<(), String> {
+        self.waiting = None;
+        self.running = false;
+
+        let stop = match mode {
+            Mode::Step => self.session.step(&mut || None),
+            Mode::Over => self.session.next(&mut || None),
+            Mode::Continue => {
+                self.running = true;
+                return self.run_chunk(connection);
+            }
+        };
+
+        self.report(connection, stop, mode)
+    }
+
+    // Runs a continued program for up to CHUNK_STEPS instructions.
+    fn run_chunk(self: &mut Self, connection: &mut Connection) -> Result<(), String> {
+        match self.session.resume_for(&mut || None, CHUNK_STEPS) {
+            Stop::Stepped => self.send_outputs(connection),
+
+            stop => {
+                self.running = false;
+                self.report(connection, stop, Mode::Continue)
+            }
+        }
+    }
+
+    fn send_outputs(self: &mut Self, connection: &mut Connection) -> Result<(), String> {
+        let outputs: Vec<f64> = self.session.outputs.drain(..).collect();
+
+        for value in outputs {
+            connection.event("output", json!({ "category": "stdout", "output": format!("{:.*}\n", self.precision, value) }))?;
+        }
+
+        Ok(())
+    }
+
+    // Sends what the program printed, then the event for why it stopped.
+    fn report(self: &mut Self, connection: &mut Connection, stop: Stop, mode: Mode) -> Result<(), String> {
+        self.send_outputs(connection)?;
+
+        match stop {
+            Stop::Halted => {
+                connection.event("exited", json!({ "exitCode": 0 }))?;
+                connection.event("terminated", json!({}))
+            }
+
+            Stop::NeedsInput(location) => {
+                self.waiting = Some(mode);
+
+                let message = format!(
+                    "Line {} needs input for {}; type a number in the debug console\n",
+                    self.session.machine.current().unwrap().lineno(), self.session.location_name(location)
+                );
+
+                connection.event("output", json!({ "category": "console", "output": message }))?;
+                self.stopped(connection, "pause", Some("Waiting for input"))
+            }
+
+            Stop::Breakpoint(_) => self.stopped(connection, "breakpoint", None),
+            Stop::Watchpoint { .. } => self.stopped(connection, "data breakpoint", None),
+            Stop::Stepped | Stop::Start => self.stopped(connection, "step", None)
+        }
+    }
+
+    fn stopped(self: &Self, connection: &mut Connection, reason: &str, description: Option<&str>) -> Result<(), String> {
+        connection.event("stopped", json!({
+            "reason": reason,
+            "description": description,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }))
+    }
+
+    // Hovers and watches show the value of acc or a memory location. In the debug console, numbers
+    // are queued as input and anything else is an rscdb command.
+    fn evaluate(self: &mut Self, connection: &mut Connection, request: &Value) -> Result<(), String> {
+        let expression = request["arguments"]["expression"].as_str().unwrap_or("").trim();
+
+        if request["arguments"]["context"].as_str() != Some("repl") {
+            let value = self.session.resolve_target(expression).map(|target| match target {
+                Target::Accum => Some(self.session.machine.accum),
+                Target::Memory(location) => self.session.machine.memory.get(&location).copied()
+            });
+
+            return match value {
+                Ok(Some(value)) => connection.respond(request, json!({ "result": value.to_string(), "variablesReference": 0 })),
+                Ok(None) => connection.respond(request, json!({ "result": "uninitialized", "variablesReference": 0 })),
+                Err(message) => connection.fail(request, &message)
+            };
+        }
+
+        let values: Vec<&str> = expression.split(|c: char| c == ',' || c.is_whitespace()).filter(|value| value.len() > 0).collect();
+
+        if values.len() > 0 && values.iter().all(|value| value.parse::<f64>().is_ok()) {
+            self.session.inputs.extend(values.iter().map(|value| value.parse::<f64>().unwrap()));
+            connection.respond(request, json!({ "result": format!("{} input(s) queued", self.session.inputs.len()), "variablesReference": 0 }))?;
+
+            return match self.waiting {
+                Some(mode) => self.run(connection, mode),
+                None => Ok(())
+            };
+        }
+
+        let before = (self.session.machine.pc, self.session.machine.steps);
+
+        match self.session.command(expression, &mut || None) {
+            Ok(reply) => connection.respond(request, json!({ "result": reply, "variablesReference": 0 }))?,
+            Err(message) => return connection.fail(request, &message)
+        }
+
+        // commands like step and continue move the program, which the editor needs to hear about
+        if (self.session.machine.pc, self.session.machine.steps) != before {
+            self.running = false;
+            let stop = if self.session.machine.is_halted() { Stop::Halted } else { Stop::Stepped };
+            self.report(connection, stop, Mode::Step)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::io::Cursor;
+
+    // Runs a session from the given requests, returning everything the server sent back.
+    fn exchange(requests: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+
+        for (idx, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(idx + 1);
+            request["type"] = json!("request");
+
+            let body = request.to_string();
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+        }
+
+        let mut output = vec![];
+        serve(Cursor::new(input), &mut output).unwrap();
+
+        let mut cursor = Cursor::new(output);
+        let mut messages = vec![];
+
+        while let Some(message) = read_message(&mut cursor).unwrap() {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+        messages.iter().filter(|message| message["event"] == event).collect()
+    }
+
+    fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+        messages.iter().find(|message| message["type"] == "response" && message["command"] == command).unwrap()
+    }
+
+    // Writes the program to a file for a launch request, which is removed along with the TempDir.
+    fn write_program(program: &str) -> (TempDir, String) {
+        let dir = TempDir::new("dap");
+        let path = dir.write("prog.rsc", program).to_string_lossy().to_string();
+        (dir, path)
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints_and_shows_variables() {
+        let (_dir, path) = write_program("LDC 3\nSTA 10\nOUT 10\nSTP");
+
+        let messages = exchange(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "setBreakpoints", "arguments": { "breakpoints": [{ "line": 3 }, { "line": 9 }] } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let breakpoints = &response(&messages, "setBreakpoints")["body"]["breakpoints"];
+        assert!(breakpoints[0] == json!({ "verified": true, "line": 3 }));
+        assert!(breakpoints[1]["verified"] == false);
+
+        assert!(events(&messages, "stopped")[0]["body"]["reason"] == "breakpoint");
+        assert!(response(&messages, "stackTrace")["body"]["stackFrames"][0]["line"] == 3);
+
+        let variables = &response(&messages, "variables")["body"]["variables"];
+        assert!(variables[0] == json!({ "name": "acc", "value": "3", "variablesReference": 0 }));
+        assert!(variables[1]["name"] == "m[10]");
+
+        assert!(events(&messages, "output")[0]["body"]["output"] == "3.00\n");
+        assert!(events(&messages, "terminated").len() == 1);
+    }
+
+    #[test]
+    fn it_takes_input_from_the_debug_console() {
+        let (_dir, path) = write_program("INP 10\nOUT 10\nSTP");
+
+        let messages = exchange(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "evaluate", "arguments": { "expression": "7", "context": "repl" } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let stopped = events(&messages, "stopped");
+        assert!(stopped.len() == 1);
+        assert!(stopped[0]["body"]["description"] == "Waiting for input");
+
+        let outputs: Vec<&Value> = events(&messages, "output").iter().map(|event| &event["body"]["output"]).collect();
+        assert!(outputs[0].as_str().unwrap().starts_with("Line 1 needs input for m[10]"));
+        assert!(outputs[1] == "7.00\n");
+        assert!(events(&messages, "terminated").len() == 1);
+    }
+
+    #[test]
+    fn it_pauses_programs_that_never_stop() {
+        let (_dir, path) = write_program("LDC 1\nBRU 1\nSTP");
+
+        let messages = exchange(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let stopped = events(&messages, "stopped");
+        assert!(stopped.len() == 2);
+        assert!(stopped.iter().all(|event| event["body"]["reason"] == "pause"));
+
+        let line = &response(&messages, "stackTrace")["body"]["stackFrames"][0]["line"];
+        assert!(*line == 1 || *line == 2);
+        assert!(events(&messages, "terminated").len() == 0);
+    }
+
+    #[test]
+    fn it_reports_launch_errors() {
+        let (_dir, path) = write_program("LDC\nSTP");
+
+        let messages = exchange(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+        ]);
+
+        let launch = response(&messages, "launch");
+        assert!(launch["success"] == false);
+        assert!(launch["message"] == "Found 1 compilation problem(s)");
+    }
+}
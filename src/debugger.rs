@@ -0,0 +1,708 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use colored::Color;
+use crate::interpreter::{Event, Machine};
+use crate::parser::{self, Instruction};
+use crate::trace;
+
+// An interactive debugger built on the interpreter. A Session holds the machine along with the
+// breakpoints, watchpoints and queued inputs, and understands gdb-style commands like "break 5",
+// "continue" and "print m[10]", so the command-line front end only has to read lines and print
+// the replies.
+//
+// Every instruction executed is also recorded in an undo log, so the debugger can run backwards:
+// reverse-step and reverse-continue put the accumulator, memory and queued inputs back how they
+// were, and last-write finds the instruction that last changed a location.
+
+// How many executed instructions are kept in the undo log. Older ones are forgotten, so a program
+// that loops for a long time can only be reversed so far.
+pub const HISTORY_LIMIT: usize = 100_000;
+
+pub const HELP: &str = "\
+Commands:
+  break <line|label|@address>    stop before the instruction on a line (b)
+  delete <line|label|@address>   remove a breakpoint
+  watch <location>               stop when a memory location is written (w)
+  unwatch <location>             remove a watchpoint
+  step                           execute one instruction (s)
+  next                           run until the instruction after this one, eg. past a loop (n)
+  continue                       run until a breakpoint, watchpoint or the end (c)
+  reverse-step                   undo the last instruction executed (rs)
+  reverse-continue               run backwards to a breakpoint, watchpoint or the start (rc)
+  last-write <location>          show which instruction last wrote a location (lw)
+  print [acc|location]           print the accumulator or a memory location, or everything (p)
+  set <acc|location> <value>     change the accumulator or a memory location
+  input <values...>              queue values for upcoming INP instructions
+  info                           list breakpoints, watchpoints and queued inputs
+  list                           show where the program is (l)
+  restart                        start the program again, keeping breakpoints
+  quit                           leave the debugger (q)
+
+Locations are written m[10], 10, or a name from rscc.toml. Addresses are instruction indices, \
+starting from 0.";
+
+// Why the program stopped running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    // A step or next completed.
+    Stepped,
+    Breakpoint(usize),
+
+    // A watched location was written by the instruction at idx. old is None if the location had
+    // never been used.
+    Watchpoint { location: u32, idx: usize, old: Option<f64>, new: f64 },
+
+    // An INP needs a value and none is queued.
+    NeedsInput(u32),
+    Halted,
+
+    // Running backwards reached the earliest instruction in the undo log.
+    Start,
+}
+
+// Something the debugger can print or change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Accum,
+    Memory(u32),
+}
+
+// An executed instruction in the undo log, with what's needed to undo it.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    // The instruction that ran, or None for a change made with the set command.
+    idx: Option<usize>,
+
+    // The step count after the instruction ran.
+    step: u64,
+    accum: f64,
+
+    // The location written, with its value before and after.
+    write: Option<(u32, Option<f64>, f64)>,
+
+    // The input consumed by an INP, which goes back on the queue when the INP is undone.
+    input: Option<f64>,
+}
+
+pub struct Session<'a> {
+    pub machine: Machine<'a>,
+    source: &'a str,
+    seed: Option<u64>,
+    names: HashMap<u32, String>,
+    labels: BTreeMap<String, usize>,
+    precision: usize,
+
+    // Instruction indices to stop before.
+    pub breakpoints: BTreeSet<usize>,
+    pub watchpoints: BTreeSet<u32>,
+    pub inputs: VecDeque<f64>,
+
+    // Values printed by OUT since the last command.
+    pub outputs: Vec<f64>,
+
+    history: VecDeque<Change>,
+
+    // Whether the undo log has dropped instructions to stay under HISTORY_LIMIT.
+    truncated: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(source: &'a str, instructions: &'a [Instruction], seed: Option<u64>, precision: usize) -> Self {
+        Session {
+            machine: Machine::new(instructions, seed),
+            source,
+            seed,
+            names: HashMap::new(),
+            labels: BTreeMap::new(),
+            precision,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            inputs: VecDeque::new(),
+            outputs: vec![],
+            history: VecDeque::new(),
+            truncated: false,
+        }
+    }
+
+    // Names for memory locations and lines, from rscc.toml.
+    pub fn with_names(mut self, names: HashMap<u32, String>, labels: BTreeMap<String, usize>) -> Self {
+        self.names = names;
+        self.labels = labels;
+        self
+    }
+
+    pub fn restart(self: &mut Self) {
+        self.machine = Machine::new(self.machine.instructions(), self.seed);
+        self.outputs.clear();
+        self.history.clear();
+        self.truncated = false;
+    }
+
+    // Executes a single instruction, taking input from the queue or read_input. Returns why the
+    // program should stop, if it should.
+    fn execute(self: &mut Self, read_input: &mut dyn FnMut() -> Option<f64>) -> Option<Stop> {
+        let idx = self.machine.pc?;
+        let instr = self.machine.current()?;
+        let accum = self.machine.accum;
+
+        // remember the old value of the location this instruction is about to write
+        let written = match instr {
+            Instruction::STA(_) | Instruction::INP(_) => instr.location(),
+            _ => None
+        };
+
+        let old = written.and_then(|location| self.machine.memory.get(&location).copied());
+        let mut input = None;
+
+        match self.machine.step() {
+            Event::Output(value) => self.outputs.push(value),
+            Event::Executed | Event::Halted => {}
+
+            Event::InputRequest(location) => {
+                match self.inputs.pop_front().or_else(&mut *read_input) {
+                    Some(value) => {
+                        self.machine.input(value);
+                        input = Some(value);
+                    }
+
+                    None => return Some(Stop::NeedsInput(location))
+                }
+            }
+        }
+
+        let write = written.map(|location| (location, old, self.machine.memory[&location]));
+        self.record(Change { idx: Some(idx), step: self.machine.steps, accum, write, input });
+
+        if let Some((location, old, new)) = write.filter(|(location, _, _)| self.watchpoints.contains(location)) {
+            return Some(Stop::Watchpoint { location, idx, old, new });
+        }
+
+        if self.machine.is_halted() {
+            return Some(Stop::Halted);
+        }
+
+        None
+    }
+
+    fn record(self: &mut Self, change: Change) {
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+            self.truncated = true;
+        }
+
+        self.history.push_back(change);
+    }
+
+    // Undoes the most recently executed instruction, along with any values set since it ran, and
+    // returns its index. Memory locations that were read before ever being written keep the random
+    // value they were given, as if it had been there all along.
+    fn unexecute(self: &mut Self) -> Option<(usize, Change)> {
+        loop {
+            let change = self.history.pop_back()?;
+
+            self.machine.accum = change.accum;
+
+            match change.write {
+                Some((location, Some(old), _)) => { self.machine.memory.insert(location, old); }
+                Some((location, None, _)) => { self.machine.memory.remove(&location); }
+                None => {}
+            }
+
+            if let Some(value) = change.input {
+                self.inputs.push_front(value);
+            }
+
+            if let Some(idx) = change.idx {
+                self.machine.pc = Some(idx);
+                self.machine.steps = change.step - 1;
+                return Some((idx, change));
+            }
+        }
+    }
+
+    pub fn reverse_step(self: &mut Self) -> Stop {
+        match self.unexecute() {
+            Some(_) => Stop::Stepped,
+            None => Stop::Start
+        }
+    }
+
+    // Runs backwards until an instruction with a breakpoint is next to run, a watched location is
+    // put back to its old value, or the undo log runs out.
+    pub fn reverse_resume(self: &mut Self) -> Stop {
+        loop {
+            let (idx, change) = match self.unexecute() {
+                Some(undone) => undone,
+                None => return Stop::Start
+            };
+
+            if let Some((location, old, new)) = change.write.filter(|(location, _, _)| self.watchpoints.contains(location)) {
+                return Stop::Watchpoint { location, idx, old, new };
+            }
+
+            if self.breakpoints.contains(&idx) {
+                return Stop::Breakpoint(idx);
+            }
+        }
+    }
+
+    // Describes the instruction in the undo log that last wrote to a location.
+    pub fn last_write(self: &Self, location: u32) -> String {
+        let instructions = self.machine.instructions();
+        let found = self.history.iter().rev().find(|change| matches!(change.write, Some((written, _, _)) if written == location));
+
+        match found {
+            Some(Change { idx: Some(idx), step, write: Some((_, old, new)), .. }) => format!(
+                "{} was last written at step {} by line {} ({}), from {} to {}",
+                self.location_name(location), step, instructions[*idx].lineno(), trace::instruction_text(&instructions[*idx]), self.value(*old), new
+            ),
+
+            Some(Change { idx: None, step, write: Some((_, old, new)), .. }) => format!(
+                "{} was last set after step {}, from {} to {}",
+                self.location_name(location), step, self.value(*old), new
+            ),
+
+            _ if self.truncated => format!("{} hasn't been written in the last {} steps", self.location_name(location), HISTORY_LIMIT),
+            _ => format!("{} hasn't been written yet", self.location_name(location))
+        }
+    }
+
+    pub fn step(self: &mut Self, read_input: &mut dyn FnMut() -> Option<f64>) -> Stop {
+        match self.machine.pc {
+            Some(_) => self.execute(read_input).unwrap_or(Stop::Stepped),
+            None => Stop::Halted
+        }
+    }
+
+    // Runs until a breakpoint, watchpoint or the end of the program. The breakpoint on the current
+    // instruction, if any, is ignored so that continuing from a breakpoint makes progress.
+    pub fn resume(self: &mut Self, read_input: &mut dyn FnMut() -> Option<f64>) -> Stop {
+        self.run_until(read_input, |_| false)
+    }
+
+    // Like resume, but gives back control with Stop::Stepped after running the given number of
+    // instructions, so a caller can look for other work between chunks of a long run.
+    pub fn resume_for(self: &mut Self, read_input: &mut dyn FnMut() -> Option<f64>, steps: u64) -> Stop {
+        let mut left = steps;
+
+        self.run_until(read_input, |_| {
+            left = left.saturating_sub(1);
+            left == 0
+        })
+    }
+
+    // Runs until the instruction after the current one is reached, which steps over a backward
+    // branch and the loop it closes.
+    pub fn next(self: &mut Self, read_input: &mut dyn FnMut() -> Option<f64>) -> Stop {
+        match self.machine.pc {
+            Some(idx) => self.run_until(read_input, |pc| pc == idx + 1),
+            None => Stop::Halted
+        }
+    }
+
+    fn run_until(self: &mut Self, read_input: &mut dyn FnMut() -> Option<f64>, mut done: impl FnMut(usize) -> bool) -> Stop {
+        if self.machine.is_halted() {
+            return Stop::Halted;
+        }
+
+        loop {
+            if let Some(stop) = self.execute(read_input) {
+                return stop;
+            }
+
+            let pc = self.machine.pc.unwrap();
+
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+
+            if done(pc) {
+                return Stop::Stepped;
+            }
+        }
+    }
+
+    // Resolves a breakpoint given as a line number, a label or @address to an instruction index.
+    pub fn resolve_breakpoint(self: &Self, arg: &str) -> Result<usize, String> {
+        let instructions = self.machine.instructions();
+
+        if let Some(address) = arg.strip_prefix('@') {
+            return match address.parse::<usize>() {
+                Ok(idx) if idx < instructions.len() => Ok(idx),
+                Ok(_) => Err(format!("There is no instruction at address {}", address)),
+                Err(_) => Err(format!("'{}' is not an address", address))
+            };
+        }
+
+        let line = match (arg.parse::<usize>(), self.labels.get(arg)) {
+            (Ok(line), _) => line,
+            (_, Some(line)) => *line,
+            _ => return Err(format!("'{}' is not a line number or label", arg))
+        };
+
+        parser::target_index(instructions, line as u32)
+            .ok_or_else(|| format!("There are no instructions on or after line {}", line))
+    }
+
+    // Parses "acc", "m[10]", "10" or a name from rscc.toml.
+    pub fn resolve_target(self: &Self, arg: &str) -> Result<Target, String> {
+        if arg == "acc" {
+            return Ok(Target::Accum);
+        }
+
+        let number = arg.strip_prefix("m[").and_then(|rest| rest.strip_suffix(']')).unwrap_or(arg);
+
+        if let Ok(location) = number.parse::<u32>() {
+            return Ok(Target::Memory(location));
+        }
+
+        match self.names.iter().find(|(_, name)| *name == arg) {
+            Some((location, _)) => Ok(Target::Memory(*location)),
+            None => Err(format!("'{}' is not acc, a memory location or a name", arg))
+        }
+    }
+
+    pub fn location_name(self: &Self, location: u32) -> String {
+        match self.names.get(&location) {
+            Some(name) => format!("{} (m[{}])", name, location),
+            None => format!("m[{}]", location)
+        }
+    }
+
+    fn value(self: &Self, value: Option<f64>) -> String {
+        match value {
+            Some(value) => value.to_string(),
+            None => "uninitialized".to_string()
+        }
+    }
+
+    fn print(self: &Self, target: Target) -> String {
+        match target {
+            Target::Accum => format!("acc = {}", self.machine.accum),
+            Target::Memory(location) => format!("{} = {}", self.location_name(location), self.value(self.machine.memory.get(&location).copied()))
+        }
+    }
+
+    // Shows the source around the instruction that will run next.
+    pub fn context(self: &Self) -> String {
+        match self.machine.current() {
+            Some(instr) => {
+                let (start, end) = parser::line_span(self.source, instr.lineno());
+                let message = format!("next (acc = {})", self.machine.accum);
+                parser::annotate_range_with_color(self.source, start, end.max(start + 1), &message, Color::Cyan)
+            }
+
+            None => "The program has finished.".to_string()
+        }
+    }
+
+    // Describes why the program stopped, followed by the source context.
+    pub fn describe_stop(self: &Self, stop: &Stop) -> String {
+        let instructions = self.machine.instructions();
+
+        let reason = match stop {
+            Stop::Stepped | Stop::Halted => None,
+            Stop::Start if self.truncated => Some(format!("Reached the earliest recorded step; only the last {} are kept", HISTORY_LIMIT)),
+            Stop::Start => Some("Reached the start of the program".to_string()),
+            Stop::Breakpoint(idx) => Some(format!("Breakpoint at line {}", instructions[*idx].lineno())),
+            Stop::NeedsInput(location) => Some(format!("Line {} needs input for {}; queue some with the input command", self.machine.current().unwrap().lineno(), self.location_name(*location))),
+
+            Stop::Watchpoint { location, idx, old, new } => Some(format!(
+                "Watchpoint: line {} changed {} from {} to {}",
+                instructions[*idx].lineno(), self.location_name(*location), self.value(*old), new
+            )),
+        };
+
+        let mut lines: Vec<String> = reason.into_iter().collect();
+        lines.push(self.context());
+        lines.join("\n")
+    }
+
+    // Runs a debugger command and returns what to print. quit is left to the caller.
+    pub fn command(self: &mut Self, line: &str, read_input: &mut dyn FnMut() -> Option<f64>) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok("".to_string())
+        };
+
+        let one_arg = || match args {
+            [arg] => Ok(*arg),
+            _ => Err(format!("{} takes one argument", command))
+        };
+
+        let stop = match command {
+            "step" | "s" => self.step(read_input),
+            "next" | "n" => self.next(read_input),
+            "continue" | "c" => self.resume(read_input),
+            "reverse-step" | "rs" => self.reverse_step(),
+            "reverse-continue" | "rc" => self.reverse_resume(),
+
+            "last-write" | "lw" => {
+                return match self.resolve_target(one_arg()?)? {
+                    Target::Memory(location) => Ok(self.last_write(location)),
+                    Target::Accum => Err("last-write takes a memory location".to_string())
+                };
+            }
+
+            "break" | "b" => {
+                let idx = self.resolve_breakpoint(one_arg()?)?;
+                self.breakpoints.insert(idx);
+                return Ok(format!("Breakpoint set at line {}", self.machine.instructions()[idx].lineno()));
+            }
+
+            "delete" => {
+                let idx = self.resolve_breakpoint(one_arg()?)?;
+
+                return match self.breakpoints.remove(&idx) {
+                    true => Ok(format!("Breakpoint at line {} deleted", self.machine.instructions()[idx].lineno())),
+                    false => Err(format!("There is no breakpoint at line {}", self.machine.instructions()[idx].lineno()))
+                };
+            }
+
+            "watch" | "w" | "unwatch" => {
+                let location = match self.resolve_target(one_arg()?)? {
+                    Target::Memory(location) => location,
+                    Target::Accum => return Err("Only memory locations can be watched".to_string())
+                };
+
+                return if command == "unwatch" {
+                    self.watchpoints.remove(&location);
+                    Ok(format!("No longer watching {}", self.location_name(location)))
+                } else {
+                    self.watchpoints.insert(location);
+                    Ok(format!("Watching {}", self.location_name(location)))
+                };
+            }
+
+            "print" | "p" => {
+                if args.len() == 0 {
+                    let mut lines = vec![self.print(Target::Accum)];
+                    let memory: BTreeMap<&u32, &f64> = self.machine.memory.iter().collect();
+
+                    for location in memory.keys() {
+                        lines.push(self.print(Target::Memory(**location)));
+                    }
+
+                    return Ok(lines.join("\n"));
+                }
+
+                let lines: Result<Vec<String>, String> = args.iter()
+                    .map(|arg| self.resolve_target(arg).map(|target| self.print(target)))
+                    .collect();
+
+                return Ok(lines?.join("\n"));
+            }
+
+            "set" => {
+                let (target, value) = match args {
+                    [target, value] | [target, "=", value] => (self.resolve_target(target)?, *value),
+                    _ => return Err("set takes a location and a value, eg. set m[10] 5".to_string())
+                };
+
+                let value = value.parse::<f64>().map_err(|_| format!("'{}' is not a number", value))?;
+
+                // the old value goes in the undo log, so running backwards puts it back
+                let write = match target {
+                    Target::Accum => None,
+                    Target::Memory(location) => Some((location, self.machine.memory.get(&location).copied(), value))
+                };
+
+                self.record(Change { idx: None, step: self.machine.steps, accum: self.machine.accum, write, input: None });
+
+                match target {
+                    Target::Accum => self.machine.accum = value,
+                    Target::Memory(location) => { self.machine.memory.insert(location, value); }
+                }
+
+                return Ok(self.print(target));
+            }
+
+            "input" | "i" => {
+                let values: Result<Vec<f64>, String> = args.iter().flat_map(|arg| arg.split(',')).filter(|arg| arg.len() > 0).map(|arg| {
+                    arg.parse::<f64>().map_err(|_| format!("'{}' is not a number", arg))
+                }).collect();
+
+                self.inputs.extend(values?);
+                return Ok(format!("{} input(s) queued", self.inputs.len()));
+            }
+
+            "info" => {
+                let instructions = self.machine.instructions();
+                let breakpoints: Vec<String> = self.breakpoints.iter().map(|idx| instructions[*idx].lineno().to_string()).collect();
+                let watchpoints: Vec<String> = self.watchpoints.iter().map(|location| self.location_name(*location)).collect();
+                let inputs: Vec<String> = self.inputs.iter().map(|value| value.to_string()).collect();
+
+                return Ok(format!(
+                    "Breakpoints on lines: {}\nWatching: {}\nQueued inputs: {}\nSteps executed: {}",
+                    none_if_empty(&breakpoints), none_if_empty(&watchpoints), none_if_empty(&inputs), self.machine.steps
+                ));
+            }
+
+            "list" | "l" => return Ok(self.context()),
+
+            "restart" => {
+                self.restart();
+                return Ok(self.context());
+            }
+
+            "help" | "h" => return Ok(HELP.to_string()),
+
+            _ => return Err(format!("Unknown command '{}'; try help", command))
+        };
+
+        // print what the program output along the way before saying where it stopped
+        let mut lines: Vec<String> = self.outputs.drain(..).map(|value| format!("{:.*}", self.precision, value)).collect();
+        lines.push(self.describe_stop(&stop));
+        Ok(lines.join("\n"))
+    }
+}
+
+fn none_if_empty(items: &[String]) -> String {
+    if items.len() == 0 {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // counts down from the input, printing each number
+    const COUNTDOWN: &str = "INP 10\nOUT 10\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP";
+
+    fn no_input() -> Option<f64> {
+        None
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints() {
+        let result = parser::parse(COUNTDOWN);
+        let mut session = Session::new(COUNTDOWN, &result.instructions, Some(0), 2);
+        session.inputs.push_back(2.0);
+
+        session.command("break 5", &mut no_input).unwrap();
+
+        assert!(session.resume(&mut no_input) == Stop::Breakpoint(4));
+        assert!(session.outputs == vec![2.0]);
+        assert!(session.machine.accum == 1.0);
+
+        assert!(session.resume(&mut no_input) == Stop::Breakpoint(4));
+        assert!(session.resume(&mut no_input) == Stop::Halted);
+        assert!(session.outputs == vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn it_steps_over_loops() {
+        let result = parser::parse(COUNTDOWN);
+        let mut session = Session::new(COUNTDOWN, &result.instructions, Some(0), 2);
+        session.inputs.push_back(3.0);
+
+        for _ in 0..5 {
+            assert!(session.step(&mut no_input) == Stop::Stepped);
+        }
+
+        // on the BPA that closes the loop
+        assert!(session.machine.pc == Some(5));
+        assert!(session.next(&mut no_input) == Stop::Stepped);
+        assert!(session.machine.pc == Some(6));
+        assert!(session.outputs == vec![3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn it_stops_at_watchpoints() {
+        let result = parser::parse(COUNTDOWN);
+        let mut session = Session::new(COUNTDOWN, &result.instructions, Some(0), 2);
+
+        session.command("watch m[10]", &mut no_input).unwrap();
+        assert!(session.resume(&mut no_input) == Stop::NeedsInput(10));
+
+        session.command("input 2", &mut no_input).unwrap();
+        assert!(session.resume(&mut no_input) == Stop::Watchpoint { location: 10, idx: 0, old: None, new: 2.0 });
+        assert!(session.resume(&mut no_input) == Stop::Watchpoint { location: 10, idx: 4, old: Some(2.0), new: 1.0 });
+    }
+
+    #[test]
+    fn it_runs_backwards() {
+        let result = parser::parse(COUNTDOWN);
+        let mut session = Session::new(COUNTDOWN, &result.instructions, Some(0), 2);
+        session.inputs.push_back(2.0);
+
+        session.command("break 3", &mut no_input).unwrap();
+        assert!(session.resume(&mut no_input) == Stop::Breakpoint(2));
+        assert!(session.resume(&mut no_input) == Stop::Breakpoint(2));
+        assert!(session.machine.memory[&10] == 1.0);
+
+        // back to the first time round the loop, then all the way to the start
+        assert!(session.reverse_resume() == Stop::Breakpoint(2));
+        assert!(session.machine.memory[&10] == 2.0);
+        assert!(session.machine.steps == 2);
+        assert!(session.reverse_step() == Stop::Stepped);
+        assert!(session.reverse_resume() == Stop::Start);
+        assert!(session.machine.pc == Some(0));
+        assert!(!session.machine.memory.contains_key(&10));
+
+        // the input goes back on the queue, so running forwards again repeats the same run
+        assert!(session.inputs == [2.0]);
+        session.outputs.clear();
+        session.breakpoints.clear();
+        assert!(session.resume(&mut no_input) == Stop::Halted);
+        assert!(session.outputs == vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn it_finds_the_last_write() {
+        let result = parser::parse(COUNTDOWN);
+        let mut session = Session::new(COUNTDOWN, &result.instructions, Some(0), 2);
+
+        assert!(session.last_write(10) == "m[10] hasn't been written yet");
+
+        session.inputs.push_back(2.0);
+        session.resume(&mut no_input);
+
+        assert!(session.command("last-write m[10]", &mut no_input) == Ok("m[10] was last written at step 10 by line 5 (STA 10), from 1 to 0".to_string()));
+        assert!(session.command("last-write acc", &mut no_input).is_err());
+    }
+
+    #[test]
+    fn it_undoes_set_when_running_backwards() {
+        let program = "LDC 1\nSTA 11\nSTP";
+        let result = parser::parse(program);
+        let mut session = Session::new(program, &result.instructions, Some(0), 2);
+        let accum = session.machine.accum;
+
+        session.step(&mut no_input);
+        session.command("set m[10] 5", &mut no_input).unwrap();
+        session.command("set acc 3", &mut no_input).unwrap();
+        assert!(session.step(&mut no_input) == Stop::Stepped);
+        assert!(session.machine.memory[&11] == 3.0);
+        assert!(session.last_write(10) == "m[10] was last set after step 1, from uninitialized to 5");
+
+        // stepping back over STA 11 keeps the values set before it, and the next step back undoes them
+        assert!(session.reverse_step() == Stop::Stepped);
+        assert!(session.machine.memory[&10] == 5.0);
+        assert!(session.machine.accum == 3.0);
+
+        assert!(session.reverse_step() == Stop::Stepped);
+        assert!(session.machine.pc == Some(0));
+        assert!(!session.machine.memory.contains_key(&10));
+        assert!(session.machine.accum == accum);
+    }
+
+    #[test]
+    fn it_prints_and_sets_values() {
+        let result = parser::parse(COUNTDOWN);
+        let labels = BTreeMap::from([("loop".to_string(), 2)]);
+        let names = HashMap::from([(10, "count".to_string())]);
+        let mut session = Session::new(COUNTDOWN, &result.instructions, Some(0), 2).with_names(names, labels);
+
+        assert!(session.command("print count", &mut no_input) == Ok("count (m[10]) = uninitialized".to_string()));
+        assert!(session.command("set m[10] = 4", &mut no_input) == Ok("count (m[10]) = 4".to_string()));
+        assert!(session.command("set acc 1.5", &mut no_input) == Ok("acc = 1.5".to_string()));
+        assert!(session.command("break loop", &mut no_input) == Ok("Breakpoint set at line 2".to_string()));
+        assert!(session.command("break @6", &mut no_input) == Ok("Breakpoint set at line 7".to_string()));
+        assert!(session.command("break nowhere", &mut no_input).is_err());
+        assert!(session.command("frobnicate", &mut no_input).is_err());
+    }
+}
@@ -0,0 +1,727 @@
+use std::collections::{BTreeSet, HashMap};
+use crate::cfg::{self, BasicBlock, Loop};
+use crate::parser::{self, Instruction};
+use crate::slice::{self, Var};
+
+// Recovers structured pseudocode (if/else, while and do/while loops) from an RSC program's branch
+// graph, eg.
+//
+// m[10] = input();
+// while (m[10] > 0) {
+//     m[11] = m[11] * m[12];
+//     ...
+// }
+//
+// Memory locations print as m[N] unless a name is known for them. Arithmetic on the accumulator is
+// folded into expressions where possible, and flow that can't be expressed with ifs and loops
+// falls back to labels and gotos.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    // The accumulator's value at the start of a block, when it can't be expressed any other way.
+    Accum,
+    Memory(u32),
+    Const(f64),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn mentions(self: &Self, location: u32) -> bool {
+        match self {
+            Expr::Memory(loc) => *loc == location,
+            Expr::Binary(_, left, right) => left.mentions(location) || right.mentions(location),
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Greater,
+    LessOrEqual,
+    Less,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    fn negate(self: &Self) -> Comparison {
+        match self {
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::GreaterOrEqual => Comparison::Less,
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+        }
+    }
+
+    fn symbol(self: &Self) -> &'static str {
+        match self {
+            Comparison::Greater => ">",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Less => "<",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+}
+
+// A comparison of an expression against zero, which is all RSC branches can do.
+#[derive(Debug, Clone, PartialEq)]
+struct Condition {
+    expr: Expr,
+    comparison: Comparison,
+}
+
+impl Condition {
+    fn negate(self: &Self) -> Condition {
+        Condition { expr: self.expr.clone(), comparison: self.comparison.negate() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Store(u32, Expr),
+    SetAccum(Expr),
+    Input(u32),
+    Print(u32),
+    Stop,
+    If(Condition, Vec<Stmt>, Vec<Stmt>),
+    Loop(Vec<Stmt>),
+    While(Condition, Vec<Stmt>),
+    DoWhile(Vec<Stmt>, Condition),
+    Break,
+    Continue,
+    Label(usize),
+    Goto(usize),
+}
+
+struct LoopContext<'a> {
+    header: usize,
+    body: &'a BTreeSet<usize>,
+    exit: Option<usize>,
+}
+
+struct Decompiler<'a> {
+    instructions: &'a [Instruction],
+    blocks: Vec<BasicBlock>,
+    loops: Vec<Loop>,
+    block_ipdoms: Vec<Option<usize>>,
+    accum_live_out: Vec<bool>,
+    emitted: BTreeSet<usize>,
+    goto_targets: BTreeSet<usize>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(instructions: &'a [Instruction]) -> Self {
+        let blocks = cfg::basic_blocks(instructions);
+        let loops = cfg::loops(&blocks);
+        let block_ipdoms = immediate_post_dominators(instructions, &blocks);
+        let accum_live_out = accum_liveness(instructions);
+
+        Decompiler {
+            instructions,
+            blocks,
+            loops,
+            block_ipdoms,
+            accum_live_out,
+            emitted: BTreeSet::new(),
+            goto_targets: BTreeSet::new(),
+        }
+    }
+
+    fn decompile(self: &mut Self) -> Vec<Stmt> {
+        if self.blocks.len() == 0 {
+            return vec![];
+        }
+
+        let mut stmts = self.sequence(0, None, None, true);
+
+        // Gotos may point at blocks that structured emission never reached, eg. the second exit of a
+        // loop. Emit those afterwards so every label exists.
+        loop {
+            let missing = self.goto_targets.iter().find(|block_idx| !self.emitted.contains(block_idx)).copied();
+
+            match missing {
+                Some(block_idx) => {
+                    let more = self.sequence(block_idx, None, None, true);
+                    stmts.extend(more);
+                }
+
+                None => break
+            }
+        }
+
+        stmts
+    }
+
+    // Emits blocks starting at start until reaching follow, the point where the enclosing
+    // construct continues.
+    fn sequence(self: &mut Self, start: usize, follow: Option<usize>, context: Option<&LoopContext>, entering: bool) -> Vec<Stmt> {
+        let mut stmts = vec![];
+        let mut cur = Some(start);
+        let mut first = entering;
+
+        while let Some(block_idx) = cur {
+            if !first {
+                if Some(block_idx) == follow {
+                    break;
+                }
+
+                if let Some(context) = context {
+                    if block_idx == context.header {
+                        stmts.push(Stmt::Continue);
+                        break;
+                    }
+
+                    if Some(block_idx) == context.exit {
+                        stmts.push(Stmt::Break);
+                        break;
+                    }
+
+                    if !context.body.contains(&block_idx) {
+                        stmts.push(self.goto(block_idx));
+                        break;
+                    }
+                }
+            }
+
+            first = false;
+
+            if self.emitted.contains(&block_idx) {
+                stmts.push(self.goto(block_idx));
+                break;
+            }
+
+            let entering_loop = self.loops.iter()
+                .find(|l| l.header == block_idx && context.map(|c| c.header) != Some(block_idx))
+                .cloned();
+
+            if let Some(l) = entering_loop {
+                let exit = self.loop_exit(&l);
+                let inner = LoopContext { header: block_idx, body: &l.body, exit };
+                let body = self.sequence(block_idx, None, Some(&inner), true);
+                stmts.push(structure_loop(body));
+                cur = exit;
+                continue;
+            }
+
+            self.emitted.insert(block_idx);
+            stmts.push(Stmt::Label(block_idx));
+
+            let (body, condition) = self.block_statements(block_idx);
+            stmts.extend(body);
+
+            let block = &self.blocks[block_idx];
+            let last = &self.instructions[block.last()];
+            let next = cfg::block_containing(&self.blocks, block.end);
+            let target = last.branch_target()
+                .and_then(|location| parser::target_index(self.instructions, location))
+                .and_then(|idx| cfg::block_containing(&self.blocks, idx));
+
+            match (last, condition) {
+                (Instruction::STP(_), _) => {
+                    stmts.push(Stmt::Stop);
+                    cur = None;
+                }
+
+                (Instruction::BRU(_), _) => {
+                    cur = target;
+                }
+
+                (_, Some(condition)) => {
+                    let join = self.block_ipdoms[block_idx];
+
+                    let taken = match target {
+                        Some(target) => self.sequence(target, join, context, false),
+                        None => vec![Stmt::Stop]
+                    };
+
+                    let not_taken = match next {
+                        Some(next) => self.sequence(next, join, context, false),
+                        None => vec![Stmt::Stop]
+                    };
+
+                    stmts.push(make_if(condition, taken, not_taken));
+                    cur = join;
+                }
+
+                _ => {
+                    cur = next;
+                }
+            }
+
+            if cur.is_none() && !matches!(last, Instruction::STP(_)) {
+                // fell off the end of the program
+                stmts.push(Stmt::Stop);
+            }
+        }
+
+        stmts
+    }
+
+    fn goto(self: &mut Self, block_idx: usize) -> Stmt {
+        self.goto_targets.insert(block_idx);
+        Stmt::Goto(block_idx)
+    }
+
+    // Picks the block a loop continues to when it finishes. If a loop has several exits, the
+    // others are reached with gotos.
+    fn loop_exit(self: &Self, l: &Loop) -> Option<usize> {
+        l.body.iter()
+            .flat_map(|block_idx| self.blocks[*block_idx].succs.iter())
+            .filter(|succ| !l.body.contains(succ))
+            .min()
+            .copied()
+    }
+
+    // Converts the instructions in a block into statements, folding accumulator arithmetic into
+    // expressions. Returns the statements and, if the block ends in a conditional branch, the
+    // branch condition.
+    fn block_statements(self: &Self, block_idx: usize) -> (Vec<Stmt>, Option<Condition>) {
+        let block = &self.blocks[block_idx];
+        let mut stmts = vec![];
+        let mut accum = Expr::Accum;
+
+        for idx in block.start..block.end {
+            let instr = &self.instructions[idx];
+
+            // Writing to a location invalidates any pending expression that reads it.
+            if let Instruction::INP(inp) = instr {
+                if accum.mentions(inp.location) {
+                    stmts.push(Stmt::SetAccum(accum));
+                    accum = Expr::Accum;
+                }
+            }
+
+            match instr {
+                Instruction::LDA(lda) => accum = Expr::Memory(lda.location),
+                Instruction::LDC(ldc) => accum = Expr::Const(ldc.value),
+
+                Instruction::ADC(adc) => {
+                    accum = if adc.value < 0.0 {
+                        Expr::Binary('-', Box::new(accum), Box::new(Expr::Const(-adc.value)))
+                    } else {
+                        Expr::Binary('+', Box::new(accum), Box::new(Expr::Const(adc.value)))
+                    }
+                }
+
+                Instruction::ADD(add) => accum = binary('+', accum, add.location),
+                Instruction::SUB(sub) => accum = binary('-', accum, sub.location),
+                Instruction::MUL(mul) => accum = binary('*', accum, mul.location),
+                Instruction::DIV(div) => accum = binary('/', accum, div.location),
+
+                Instruction::STA(sta) => {
+                    if accum != Expr::Memory(sta.location) {
+                        stmts.push(Stmt::Store(sta.location, accum));
+                    }
+
+                    accum = Expr::Memory(sta.location);
+                }
+
+                Instruction::INP(inp) => stmts.push(Stmt::Input(inp.location)),
+
+                Instruction::OUT(out) => {
+                    stmts.push(Stmt::Print(out.location));
+                    accum = Expr::Memory(out.location);
+                }
+
+                _ => ()
+            }
+        }
+
+        let last = &self.instructions[block.last()];
+
+        let condition = match last {
+            Instruction::BPA(_) => Some(Comparison::Greater),
+            Instruction::BNA(_) => Some(Comparison::Less),
+            Instruction::BZA(_) => Some(Comparison::Equal),
+            _ => None
+        };
+
+        if self.accum_live_out[block.last()] && accum != Expr::Accum {
+            stmts.push(Stmt::SetAccum(accum));
+            accum = Expr::Accum;
+        }
+
+        (stmts, condition.map(|comparison| Condition { expr: accum, comparison }))
+    }
+}
+
+fn binary(op: char, accum: Expr, location: u32) -> Expr {
+    Expr::Binary(op, Box::new(accum), Box::new(Expr::Memory(location)))
+}
+
+fn make_if(condition: Condition, then_branch: Vec<Stmt>, else_branch: Vec<Stmt>) -> Stmt {
+    if then_branch.len() == 0 {
+        Stmt::If(condition.negate(), else_branch, vec![])
+    } else {
+        Stmt::If(condition, then_branch, else_branch)
+    }
+}
+
+// Turns an infinite loop with breaks into a while or do/while loop where the shape allows it.
+fn structure_loop(mut body: Vec<Stmt>) -> Stmt {
+    let labels: Vec<Stmt> = body.iter().take_while(|stmt| matches!(stmt, Stmt::Label(_))).cloned().collect();
+    body.drain(0..labels.len());
+
+    // if (c) { ...; continue; } break;  =>  if (!c) break; ...
+    if let [.., Stmt::If(_, then_branch, else_branch), Stmt::Break] = body.as_slice() {
+        if else_branch.len() == 0 && then_branch.len() > 1 && then_branch.last() == Some(&Stmt::Continue) {
+            body.pop();
+
+            if let Some(Stmt::If(condition, mut then_branch, _)) = body.pop() {
+                then_branch.pop();
+                body.push(Stmt::If(condition.negate(), vec![Stmt::Break], vec![]));
+                body.extend(then_branch);
+                body.push(Stmt::Continue);
+            }
+        }
+    }
+
+    // ...; if (c) continue; break;  =>  do { ... } while (c)
+    if let [.., Stmt::If(condition, then_branch, else_branch), Stmt::Break] = body.as_slice() {
+        if then_branch.as_slice() == [Stmt::Continue] && else_branch.len() == 0 {
+            let condition = condition.clone();
+            body.truncate(body.len() - 2);
+            return wrap_labels(labels, Stmt::DoWhile(body, condition));
+        }
+    }
+
+    if body.last() == Some(&Stmt::Continue) {
+        body.pop();
+    }
+
+    // if (c) break; ...  =>  while (!c) { ... }
+    if let Some(Stmt::If(condition, then_branch, else_branch)) = body.first() {
+        if then_branch.as_slice() == [Stmt::Break] && else_branch.len() == 0 {
+            let condition = condition.negate();
+            body.remove(0);
+            return wrap_labels(labels, Stmt::While(condition, body));
+        }
+    }
+
+    wrap_labels(labels, Stmt::Loop(body))
+}
+
+fn wrap_labels(labels: Vec<Stmt>, stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::While(condition, mut body) => {
+            let mut with_labels = labels;
+            with_labels.append(&mut body);
+            Stmt::While(condition, with_labels)
+        }
+
+        Stmt::DoWhile(mut body, condition) => {
+            let mut with_labels = labels;
+            with_labels.append(&mut body);
+            Stmt::DoWhile(with_labels, condition)
+        }
+
+        Stmt::Loop(mut body) => {
+            let mut with_labels = labels;
+            with_labels.append(&mut body);
+            Stmt::Loop(with_labels)
+        }
+
+        other => other
+    }
+}
+
+// For each block, the block where both sides of its branch (if any) meet again.
+fn immediate_post_dominators(instructions: &[Instruction], blocks: &[BasicBlock]) -> Vec<Option<usize>> {
+    let pdoms = cfg::post_dominators_within(instructions);
+
+    blocks.iter().map(|block| {
+        let last = block.last();
+
+        // the nearest strict post-dominator is the one post-dominated by all the others
+        pdoms[last].iter()
+            .filter(|idx| **idx != last)
+            .max_by_key(|idx| pdoms[**idx].len())
+            .and_then(|idx| cfg::block_containing(blocks, *idx))
+    }).collect()
+}
+
+// Whether the accumulator's value may still be read after each instruction.
+fn accum_liveness(instructions: &[Instruction]) -> Vec<bool> {
+    let mut live_in = vec![false; instructions.len()];
+    let mut live_out = vec![false; instructions.len()];
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for idx in (0..instructions.len()).rev() {
+            let out = cfg::successors(instructions, idx).iter().any(|succ| live_in[*succ]);
+            let instr = &instructions[idx];
+            let uses = slice::uses(instr).contains(&Var::Accum);
+            let defs = slice::defs(instr).contains(&Var::Accum);
+            let in_ = uses || (out && !defs);
+
+            if out != live_out[idx] || in_ != live_in[idx] {
+                live_out[idx] = out;
+                live_in[idx] = in_;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
+struct Printer<'a> {
+    instructions: &'a [Instruction],
+    blocks: Vec<BasicBlock>,
+    names: &'a HashMap<u32, String>,
+    goto_targets: BTreeSet<usize>,
+    lines: Vec<String>,
+}
+
+impl<'a> Printer<'a> {
+    fn memory(self: &Self, location: u32) -> String {
+        match self.names.get(&location) {
+            Some(name) => name.clone(),
+            None => format!("m[{}]", location)
+        }
+    }
+
+    fn label(self: &Self, block_idx: usize) -> String {
+        format!("L{}", self.instructions[self.blocks[block_idx].start].lineno())
+    }
+
+    fn expr(self: &Self, expr: &Expr) -> String {
+        self.expr_prec(expr, 0)
+    }
+
+    fn expr_prec(self: &Self, expr: &Expr, parent_prec: u8) -> String {
+        match expr {
+            Expr::Accum => "acc".to_string(),
+            Expr::Memory(location) => self.memory(*location),
+            Expr::Const(value) => value.to_string(),
+
+            Expr::Binary(op, left, right) => {
+                let prec = if *op == '+' || *op == '-' { 1 } else { 2 };
+
+                // the right operand needs parentheses at equal precedence, eg. a - (b - c)
+                let result = format!("{} {} {}", self.expr_prec(left, prec), op, self.expr_prec(right, prec + 1));
+
+                if prec < parent_prec {
+                    format!("({})", result)
+                } else {
+                    result
+                }
+            }
+        }
+    }
+
+    fn condition(self: &Self, condition: &Condition) -> String {
+        format!("{} {} 0", self.expr(&condition.expr), condition.comparison.symbol())
+    }
+
+    fn line(self: &mut Self, depth: usize, text: String) {
+        self.lines.push(format!("{}{}", "    ".repeat(depth), text));
+    }
+
+    fn stmts(self: &mut Self, stmts: &[Stmt], depth: usize) {
+        for stmt in stmts {
+            self.stmt(stmt, depth);
+        }
+    }
+
+    fn stmt(self: &mut Self, stmt: &Stmt, depth: usize) {
+        match stmt {
+            Stmt::Store(location, expr) => {
+                let text = format!("{} = {};", self.memory(*location), self.expr(expr));
+                self.line(depth, text);
+            }
+
+            Stmt::SetAccum(expr) => {
+                let text = format!("acc = {};", self.expr(expr));
+                self.line(depth, text);
+            }
+
+            Stmt::Input(location) => {
+                let text = format!("{} = input();", self.memory(*location));
+                self.line(depth, text);
+            }
+
+            Stmt::Print(location) => {
+                let text = format!("print({});", self.memory(*location));
+                self.line(depth, text);
+            }
+
+            Stmt::Stop => self.line(depth, "stop;".to_string()),
+            Stmt::Break => self.line(depth, "break;".to_string()),
+            Stmt::Continue => self.line(depth, "continue;".to_string()),
+
+            Stmt::If(condition, then_branch, else_branch) => {
+                let text = format!("if ({}) {{", self.condition(condition));
+                self.line(depth, text);
+                self.stmts(then_branch, depth + 1);
+
+                if else_branch.len() > 0 {
+                    self.line(depth, "} else {".to_string());
+                    self.stmts(else_branch, depth + 1);
+                }
+
+                self.line(depth, "}".to_string());
+            }
+
+            Stmt::Loop(body) => {
+                self.line(depth, "while (true) {".to_string());
+                self.stmts(body, depth + 1);
+                self.line(depth, "}".to_string());
+            }
+
+            Stmt::While(condition, body) => {
+                let text = format!("while ({}) {{", self.condition(condition));
+                self.line(depth, text);
+                self.stmts(body, depth + 1);
+                self.line(depth, "}".to_string());
+            }
+
+            Stmt::DoWhile(body, condition) => {
+                self.line(depth, "do {".to_string());
+                self.stmts(body, depth + 1);
+                let text = format!("}} while ({});", self.condition(condition));
+                self.line(depth, text);
+            }
+
+            Stmt::Label(block_idx) => {
+                if self.goto_targets.contains(block_idx) {
+                    let text = format!("{}:", self.label(*block_idx));
+                    self.line(depth.saturating_sub(1), text);
+                }
+            }
+
+            Stmt::Goto(block_idx) => {
+                let text = format!("goto {};", self.label(*block_idx));
+                self.line(depth, text);
+            }
+        }
+    }
+}
+
+// Decompiles the program into pseudocode, using the given names for memory locations where
+// available.
+pub fn decompile(instructions: &[Instruction], names: &HashMap<u32, String>) -> String {
+    let mut decompiler = Decompiler::new(instructions);
+    let stmts = decompiler.decompile();
+
+    let mut printer = Printer {
+        instructions,
+        blocks: decompiler.blocks,
+        names,
+        goto_targets: decompiler.goto_targets,
+        lines: vec![],
+    };
+
+    printer.stmts(&stmts, 0);
+    printer.lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompile_str(program: &str) -> String {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+        decompile(&result.instructions, &HashMap::new())
+    }
+
+    #[test]
+    fn it_folds_straight_line_arithmetic() {
+        let code = decompile_str("LDC 5\nSTA 10\nLDC 2\nSTA 11\nLDA 10\nDIV 11\nSTA 12\nOUT 12\nSTP");
+        assert!(code == "m[10] = 5;\nm[11] = 2;\nm[12] = m[10] / m[11];\nprint(m[12]);\nstop;");
+    }
+
+    #[test]
+    fn it_recovers_if_else() {
+        let code = decompile_str(
+            "INP 10\nLDA 10\nBPA 7\nLDC -1\nSTA 11\nBRU 9\nLDC 1\nSTA 11\nOUT 11\nSTP"
+        );
+
+        assert!(code == [
+            "m[10] = input();",
+            "if (m[10] > 0) {",
+            "    m[11] = 1;",
+            "} else {",
+            "    m[11] = -1;",
+            "}",
+            "print(m[11]);",
+            "stop;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn it_stops_on_branches_past_the_end() {
+        let code = decompile_str("INP 10\nLDA 10\nBPA 99\nOUT 10\nSTP");
+
+        assert!(code == [
+            "m[10] = input();",
+            "if (m[10] > 0) {",
+            "    stop;",
+            "}",
+            "print(m[10]);",
+            "stop;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn it_recovers_while_loops() {
+        // multiplies m[11] by m[12] m[10] times
+        let code = decompile_str(
+            "INP 10\nINP 12\nLDC 1\nSTA 11\nLDC 1\nSTA 13\nLDA 10\nBZA 17\nLDA 11\nMUL 12\nSTA 11\nLDA 10\nSUB 13\nSTA 10\nBRU 7\nSTP\nOUT 11\nSTP"
+        );
+
+        assert!(code == [
+            "m[10] = input();",
+            "m[12] = input();",
+            "m[11] = 1;",
+            "m[13] = 1;",
+            "while (m[10] != 0) {",
+            "    m[11] = m[11] * m[12];",
+            "    m[10] = m[10] - m[13];",
+            "}",
+            "print(m[11]);",
+            "stop;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn it_recovers_do_while_loops() {
+        let code = decompile_str("INP 10\nOUT 10\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP");
+
+        assert!(code == [
+            "m[10] = input();",
+            "do {",
+            "    print(m[10]);",
+            "    m[10] = m[10] - 1;",
+            "} while (m[10] > 0);",
+            "stop;",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn it_uses_known_names() {
+        let result = parser::parse("INP 10\nLDA 10\nADD 10\nSTA 11\nOUT 11\nSTP");
+        let names = HashMap::from([(10, "x".to_string()), (11, "doubled".to_string())]);
+        let code = decompile(&result.instructions, &names);
+
+        assert!(code == "x = input();\ndoubled = x + x;\nprint(doubled);\nstop;");
+    }
+
+    #[test]
+    fn it_falls_back_to_gotos_for_irreducible_flow() {
+        // jumps into the middle of a loop
+        let code = decompile_str("INP 10\nLDA 10\nBPA 6\nLDC 1\nSTA 11\nOUT 10\nLDA 11\nBPA 4\nSTP");
+
+        assert!(code.contains("goto L"));
+        assert!(code.lines().any(|line| line.trim().starts_with('L') && line.ends_with(':')));
+    }
+}
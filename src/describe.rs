@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use crate::cfg::{self, BasicBlock};
+use crate::parser::{self, Instruction};
+
+// Explains a program in plain English for students reading example code, eg.
+//
+// line 3: store the accumulator into memory location 10
+// ...
+// lines 5–9 repeat while m[10] is positive
+//
+// Each line is described on its own, following the instruction documentation in parser.rs, and
+// then loops and skipped sections are summarized. Memory locations use their names from rscc.toml
+// where known.
+
+struct Describer<'a> {
+    instructions: &'a [Instruction],
+    names: &'a HashMap<u32, String>,
+}
+
+impl<'a> Describer<'a> {
+    // How a location is referred to in a sentence about a single instruction.
+    fn location(self: &Self, location: u32) -> String {
+        match self.names.get(&location) {
+            Some(name) => name.clone(),
+            None => format!("memory location {}", location)
+        }
+    }
+
+    // How a location is referred to in a summary, which is terser.
+    fn short_location(self: &Self, location: u32) -> String {
+        match self.names.get(&location) {
+            Some(name) => name.clone(),
+            None => format!("m[{}]", location)
+        }
+    }
+
+    fn line(self: &Self, instr: &Instruction) -> String {
+        let condition = match instr {
+            Instruction::BPA(_) => " if the accumulator is positive",
+            Instruction::BNA(_) => " if the accumulator is negative",
+            Instruction::BZA(_) => " if the accumulator is zero",
+            _ => ""
+        };
+
+        match instr {
+            Instruction::LDA(lda) => format!("load the value in {} into the accumulator", self.location(lda.location)),
+            Instruction::LDC(ldc) => format!("load the constant {} into the accumulator", ldc.value),
+            Instruction::STA(sta) => format!("store the accumulator into {}", self.location(sta.location)),
+            Instruction::INP(inp) => format!("read a number from the user into {}", self.location(inp.location)),
+            Instruction::OUT(out) => format!("print the value in {}", self.location(out.location)),
+            Instruction::ADC(adc) if adc.value < 0.0 => format!("subtract {} from the accumulator", -adc.value),
+            Instruction::ADC(adc) => format!("add {} to the accumulator", adc.value),
+            Instruction::ADD(add) => format!("add the value in {} to the accumulator", self.location(add.location)),
+            Instruction::SUB(sub) => format!("subtract the value in {} from the accumulator", self.location(sub.location)),
+            Instruction::MUL(mul) => format!("multiply the accumulator by the value in {}", self.location(mul.location)),
+            Instruction::DIV(div) => format!("divide the accumulator by the value in {}", self.location(div.location)),
+            Instruction::STP(_) => "stop the program".to_string(),
+
+            _ => {
+                let target = instr.branch_target().unwrap();
+
+                match parser::target_index(self.instructions, target) {
+                    Some(idx) => format!("jump to line {}{}", self.instructions[idx].lineno(), condition),
+                    None => format!("stop the program{}", condition)
+                }
+            }
+        }
+    }
+
+    // What the accumulator holds when the branch at idx runs, eg. "m[10]", judging from the
+    // instructions before it in the same block.
+    fn tested_value(self: &Self, block: &BasicBlock, idx: usize) -> String {
+        for instr in self.instructions[block.start..idx].iter().rev() {
+            match instr {
+                // STA and OUT leave the accumulator equal to the location
+                Instruction::LDA(lda) => return self.short_location(lda.location),
+                Instruction::STA(sta) => return self.short_location(sta.location),
+                Instruction::OUT(out) => return self.short_location(out.location),
+                Instruction::LDC(ldc) => return ldc.value.to_string(),
+                Instruction::INP(_) => continue,
+                _ => break
+            }
+        }
+
+        "the accumulator".to_string()
+    }
+
+    fn condition(self: &Self, block: &BasicBlock) -> String {
+        let idx = block.last();
+
+        let sign = match &self.instructions[idx] {
+            Instruction::BPA(_) => "positive",
+            Instruction::BNA(_) => "negative",
+            _ => "zero"
+        };
+
+        format!("{} is {}", self.tested_value(block, idx), sign)
+    }
+
+    fn lines(self: &Self, first: usize, last: usize) -> String {
+        let first = self.instructions[first].lineno();
+        let last = self.instructions[last].lineno();
+
+        if first == last {
+            format!("line {}", first)
+        } else {
+            format!("lines {}–{}", first, last)
+        }
+    }
+
+    fn summaries(self: &Self) -> Vec<String> {
+        let instructions = self.instructions;
+        let blocks = cfg::basic_blocks(instructions);
+        let loops = cfg::loops(&blocks);
+        let mut summaries = vec![];
+
+        for l in &loops {
+            let first = l.body.iter().map(|b| blocks[*b].start).min().unwrap();
+            let last = l.body.iter().map(|b| blocks[*b].last()).max().unwrap();
+
+            // the first conditional branch that can leave the loop, or the program, decides how
+            // long it runs
+            let exit = l.body.iter().map(|b| &blocks[*b]).find(|block| {
+                let destinations = block.succs.len() + block.exits as usize;
+                destinations == 2 && (block.exits || block.succs.iter().any(|succ| !l.body.contains(succ)))
+            });
+
+            let summary = match exit {
+                Some(block) => {
+                    let target = instructions[block.last()].branch_target()
+                        .and_then(|location| parser::target_index(instructions, location))
+                        .and_then(|idx| cfg::block_containing(&blocks, idx));
+
+                    // if taking the branch stays in the loop, the loop continues while the
+                    // condition holds, otherwise it stops as soon as it does
+                    let stays = target.is_some_and(|t| l.body.contains(&t));
+                    let keyword = if stays { "while" } else { "until" };
+
+                    format!("{} repeat {} {}", self.lines(first, last), keyword, self.condition(block))
+                }
+
+                None => format!("{} repeat forever", self.lines(first, last))
+            };
+
+            summaries.push(summary);
+        }
+
+        // forward branches that skip over a run of lines, outside of loop exits
+        for (block_idx, block) in blocks.iter().enumerate() {
+            let idx = block.last();
+
+            if !matches!(instructions[idx], Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_)) {
+                continue;
+            }
+
+            let target = instructions[idx].branch_target()
+                .and_then(|location| parser::target_index(instructions, location))
+                .unwrap_or(instructions.len());
+
+            let exits_loop = loops.iter().any(|l| {
+                l.body.contains(&block_idx) && (block.exits || block.succs.iter().any(|succ| !l.body.contains(succ)))
+            });
+
+            if target > idx + 1 && !exits_loop {
+                let verb = if target - 1 > idx + 1 { "are" } else { "is" };
+                summaries.push(format!("{} {} skipped when {}", self.lines(idx + 1, target - 1), verb, self.condition(block)));
+            }
+        }
+
+        summaries
+    }
+}
+
+// Describes each line of the program, followed by summaries of its loops and conditionally
+// skipped sections.
+pub fn describe(instructions: &[Instruction], names: &HashMap<u32, String>) -> String {
+    let describer = Describer { instructions, names };
+
+    let mut lines: Vec<String> = instructions.iter()
+        .map(|instr| format!("line {}: {}", instr.lineno(), describer.line(instr)))
+        .collect();
+
+    let summaries = describer.summaries();
+
+    if summaries.len() > 0 {
+        lines.push("".to_string());
+        lines.extend(summaries);
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe_program(program: &str) -> Vec<String> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+        describe(&result.instructions, &HashMap::new()).lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn it_describes_each_line() {
+        let lines = describe_program("INP 10\nLDA 10\nADC -1\nMUL 10\nSTA 11\nOUT 11\nSTP");
+
+        assert!(lines == vec![
+            "line 1: read a number from the user into memory location 10",
+            "line 2: load the value in memory location 10 into the accumulator",
+            "line 3: subtract 1 from the accumulator",
+            "line 4: multiply the accumulator by the value in memory location 10",
+            "line 5: store the accumulator into memory location 11",
+            "line 6: print the value in memory location 11",
+            "line 7: stop the program",
+        ]);
+    }
+
+    #[test]
+    fn it_summarizes_loops() {
+        // counts down from the input, printing each number
+        let lines = describe_program("INP 10\nOUT 10\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP");
+
+        assert!(lines[5] == "line 6: jump to line 2 if the accumulator is positive");
+        assert!(lines.last().unwrap() == "lines 2–6 repeat while m[10] is positive");
+    }
+
+    #[test]
+    fn it_summarizes_loops_tested_at_the_top() {
+        let lines = describe_program("INP 10\nLDA 10\nBZA 8\nADC -1\nSTA 10\nOUT 10\nBRU 2\nSTP");
+        assert!(lines.last().unwrap() == "lines 2–7 repeat until m[10] is zero");
+    }
+
+    #[test]
+    fn it_summarizes_loops_left_by_branching_past_the_end() {
+        let lines = describe_program("INP 10\nLDA 10\nBZA 99\nADC -1\nSTA 10\nOUT 10\nBRU 2\nSTP");
+
+        assert!(lines.last().unwrap() == "lines 2–7 repeat until m[10] is zero");
+        assert!(lines.iter().all(|line| !line.contains("skipped")));
+    }
+
+    #[test]
+    fn it_summarizes_skipped_lines() {
+        let result = parser::parse("INP 10\nLDA 10\nBNA 6\nOUT 10\nSTP\nSTP");
+        let names = HashMap::from([(10, "x".to_string())]);
+        let description = describe(&result.instructions, &names);
+
+        assert!(description.contains("line 2: load the value in x into the accumulator"));
+        assert!(description.ends_with("lines 4–5 are skipped when x is negative"));
+    }
+}
@@ -1,12 +1,18 @@
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::FuncId;
+use cranelift_module::{DataId, FuncId};
 use cranelift_object::{ObjectBuilder, ObjectModule, ObjectProduct};
 use target_lexicon::Triple;
-use rand::Rng;
+use crate::compiler::CompileOptions;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
+use std::ffi::c_void;
+use std::fs::File;
 use std::io;
-use std::io::Write;
+use std::io::{BufRead, Write};
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 pub struct RSCObjectModule {
     pub product: ObjectProduct
@@ -14,10 +20,239 @@ pub struct RSCObjectModule {
 
 pub struct RSCJITModule {
     pub module: JITModule,
-    pub main_id: FuncId
+    pub main_id: FuncId,
+    pub counts_id: Option<DataId>,
+}
+
+impl RSCJITModule {
+    // Runs the program against the given runtime and returns its exit status. Each run gets its
+    // own runtime, so several programs can run at once, eg. on different threads.
+    pub fn run(self: &Self, runtime: &mut dyn RscRuntime) -> i32 {
+        let main = self.module.get_finalized_function(self.main_id);
+        let main = unsafe { std::mem::transmute::<*const u8, extern "C" fn(*mut c_void) -> i32>(main) };
+
+        // the context is a thin pointer to the fat one, which the rsc_* functions below unwrap
+        let mut runtime = runtime;
+        main(&mut runtime as *mut &mut dyn RscRuntime as *mut c_void)
+    }
+
+    // How many times each instruction has executed, if the module was compiled for profiling.
+    pub fn counts(self: &Self) -> Option<Vec<u64>> {
+        let (data, len) = self.module.get_finalized_data(self.counts_id?);
+        let counts = unsafe { std::slice::from_raw_parts(data as *const u64, len / 8) };
+        Some(counts.to_vec())
+    }
+}
+
+// Where a JIT-compiled program's output goes, and where its input and random values come from.
+// StdRuntime uses the terminal; embedders can implement this to capture output or feed input.
+pub trait RscRuntime {
+    fn output(self: &mut Self, value: f64);
+
+    // Returns the value for INP, or the status to stop the program with if there isn't one, eg.
+    // END_OF_INPUT_STATUS.
+    fn input(self: &mut Self) -> Result<f64, i32>;
+
+    // The starting value of the accumulator and of each memory location.
+    fn random(self: &mut Self) -> f64;
+
+    // Called before the program starts.
+    fn start(self: &mut Self) {}
+
+    // Called at the start of each basic block of programs compiled with CompileOptions::checks.
+    // Returns zero to keep going, or the status to stop the program with.
+    fn check(self: &mut Self, _line: usize) -> i32 {
+        0
+    }
+
+    // Called when the program stops with one of the statuses in compiler.rs because of the given
+    // line, other than the ones check returns.
+    fn error(self: &mut Self, status: i32, line: usize) {
+        eprintln!("{}", error_message(status, line));
+    }
+}
+
+// Settings for the runtime. The JIT passes them to StdRuntime, while compiled executables have
+// them baked into rsc.c at build time.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeOptions {
+    pub seed: Option<u64>,
+    pub precision: Option<usize>,
+
+    // Limits for rscc run, which programs compiled with CompileOptions::checks find out about
+    // through rsc_check. These aren't read from rscc.toml.
+    pub timeout: Option<Duration>,
+    pub max_output: Option<u64>,
+
+    // Scripted input for rscc run: values for INP to read before it prompts, whether to print the
+    // prompt, and a file to save every value read to. Compiled executables take these as
+    // command-line flags instead.
+    pub inputs: Vec<f64>,
+    pub no_prompt: bool,
+    pub record: Option<String>,
+}
+
+pub const DEFAULT_PRECISION: usize = 2;
+
+// INP gives up with INVALID_INPUT_STATUS after this many invalid entries in a row.
+pub const MAX_INVALID_ENTRIES: u32 = 5;
+
+// How many checks go by between looks at the clock.
+const CLOCK_INTERVAL: u64 = 1024;
+
+// The runtime rscc run uses, which prints to standard output and prompts for input on standard
+// input. The interpreter engine uses it too, so both engines behave the same on the terminal.
+pub struct StdRuntime {
+    rng: StdRng,
+    precision: usize,
+    timeout: Option<Duration>,
+    max_output: Option<u64>,
+    inputs: VecDeque<f64>,
+    prompt: bool,
+    record: Option<File>,
+
+    // The state of the current run, reset by start.
+    deadline: Option<Instant>,
+    outputs: u64,
+    checks: u64,
+}
+
+impl StdRuntime {
+    pub fn new(options: &RuntimeOptions) -> Result<Self, String> {
+        let record = match &options.record {
+            Some(path) => Some(File::create(path).map_err(|e| format!("Couldn't create {}: {}", path, e))?),
+            None => None
+        };
+
+        Ok(StdRuntime {
+            rng: match options.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy()
+            },
+            precision: options.precision.unwrap_or(DEFAULT_PRECISION),
+            timeout: options.timeout,
+            max_output: options.max_output,
+            inputs: options.inputs.iter().copied().collect(),
+            prompt: !options.no_prompt,
+            record,
+            deadline: None,
+            outputs: 0,
+            checks: 0,
+        })
+    }
+
+    fn read_input(self: &Self) -> Result<f64, i32> {
+        read_input(&mut io::stdin().lock(), &mut io::stdout(), self.prompt)
+    }
+}
+
+// Prompts for an INP value until a valid number is entered, or the input ends, or there have
+// been too many invalid entries. Each line has to hold exactly one number, which rsc_input in
+// rsc.c checks the same way.
+pub(crate) fn read_input(input: &mut dyn BufRead, output: &mut dyn Write, prompt: bool) -> Result<f64, i32> {
+    let mut invalid = 0;
+
+    loop {
+        if prompt {
+            write!(output, "Input: ").and_then(|_| output.flush()).map_err(|_| crate::compiler::INPUT_ERROR_STATUS)?;
+        }
+
+        let mut buffer = String::new();
+
+        match input.read_line(&mut buffer) {
+            Ok(0) => return Err(crate::compiler::END_OF_INPUT_STATUS),
+
+            // a line that isn't UTF-8 is just an invalid entry, like in rsc.c
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(_) => return Err(crate::compiler::INPUT_ERROR_STATUS),
+
+            Ok(_) => {
+                let trimed_buffer = buffer.trim();
+
+                match trimed_buffer.parse::<f64>() {
+                    Ok(float) if float.is_finite() => return Ok(float),
+                    _ => {}
+                }
+            }
+        }
+
+        invalid += 1;
+
+        if invalid == MAX_INVALID_ENTRIES {
+            return Err(crate::compiler::INVALID_INPUT_STATUS);
+        }
+
+        writeln!(output, "Invalid entry, try again.").map_err(|_| crate::compiler::INPUT_ERROR_STATUS)?;
+    }
+}
+
+impl RscRuntime for StdRuntime {
+    // Prints an OUT value with the configured precision. Values past the output limit are
+    // dropped, and check stops the program.
+    fn output(self: &mut Self, value: f64) {
+        self.outputs += 1;
+
+        if self.max_output.map_or(true, |max_output| self.outputs <= max_output) {
+            println!("{:.*}", self.precision, value);
+        }
+    }
+
+    // Takes the next scripted value, or reads one from standard input once they run out. Values
+    // are saved one per line as they're read, so a run that's stopped part way can be replayed
+    // up to that point.
+    fn input(self: &mut Self) -> Result<f64, i32> {
+        let value = match self.inputs.pop_front() {
+            Some(value) => value,
+            None => self.read_input()?
+        };
+
+        if let Some(record) = &mut self.record {
+            writeln!(record, "{}", format_input(value)).map_err(|_| crate::compiler::INPUT_ERROR_STATUS)?;
+        }
+
+        Ok(value)
+    }
+
+    fn random(self: &mut Self) -> f64 {
+        self.rng.gen_range((-0x10000 as f64)..(0x10000 as f64))
+    }
+
+    // Starts the clock for the timeout and the count for the output limit.
+    fn start(self: &mut Self) {
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.outputs = 0;
+        self.checks = 0;
+    }
+
+    // Prints which limit the program hit, if any.
+    fn check(self: &mut Self, line: usize) -> i32 {
+        if let Some(max_output) = self.max_output.filter(|max_output| self.outputs > *max_output) {
+            eprintln!("output limit of {} value(s) exceeded at line {}", max_output, line);
+            return crate::compiler::OUTPUT_LIMIT_STATUS;
+        }
+
+        self.checks += 1;
+
+        if self.checks % CLOCK_INTERVAL != 0 {
+            return 0;
+        }
+
+        match (self.timeout, self.deadline) {
+            (Some(timeout), Some(deadline)) if Instant::now() >= deadline => {
+                eprintln!("time limit of {:?} exceeded at line {}", timeout, line);
+                crate::compiler::TIMEOUT_STATUS
+            }
+
+            _ => 0
+        }
+    }
 }
 
 pub fn emit_object_module(triple: Triple, instructions: Vec<crate::parser::Instruction>) -> RSCObjectModule {
+    emit_object_module_with_options(triple, instructions, &CompileOptions::default())
+}
+
+pub fn emit_object_module_with_options(triple: Triple, instructions: Vec<crate::parser::Instruction>, options: &CompileOptions) -> RSCObjectModule {
     let mut shared_builder = settings::builder();
     shared_builder.enable("is_pic").unwrap();
 
@@ -27,12 +262,16 @@ pub fn emit_object_module(triple: Triple, instructions: Vec<crate::parser::Instr
     let obj_builder = ObjectBuilder::new(isa, "main", cranelift_module::default_libcall_names()).unwrap();
     let mut module = ObjectModule::new(obj_builder);
 
-    crate::compiler::compile(instructions, &mut module);
+    crate::compiler::compile_with_options(instructions, &mut module, options);
 
     RSCObjectModule { product: module.finish() }
 }
 
-pub fn emit_jit_module(instructions: Vec<crate::parser::Instruction>, cb: Option<&dyn Fn(&mut JITBuilder)>) -> RSCJITModule {
+pub fn emit_jit_module(instructions: Vec<crate::parser::Instruction>) -> RSCJITModule {
+    emit_jit_module_with_options(instructions, &CompileOptions::default())
+}
+
+pub fn emit_jit_module_with_options(instructions: Vec<crate::parser::Instruction>, options: &CompileOptions) -> RSCJITModule {
     let triple = Triple::from_str(crate::built_info::TARGET).unwrap();
     let mut shared_builder = settings::builder();
 
@@ -45,57 +284,106 @@ pub fn emit_jit_module(instructions: Vec<crate::parser::Instruction>, cb: Option
     let isa = isa_builder.finish(shared_flags).unwrap();
     let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
 
-    // fill in these helper functions with rust implementations so we can actually run the code
+    // point the runtime functions at ones that forward to the RscRuntime passed to run
     builder.symbol("rsc_init", rsc_init as *const u8);
     builder.symbol("rsc_rand", rsc_rand as *const u8);
     builder.symbol("rsc_out", rsc_out as *const u8);
     builder.symbol("rsc_input", rsc_input as *const u8);
-
-    match cb {
-        Some(f) => f(&mut builder),
-        None => ()
-    }
+    builder.symbol("rsc_error", rsc_error as *const u8);
+    builder.symbol("rsc_check", rsc_check as *const u8);
 
     let mut module = JITModule::new(builder);
-    let main_id = crate::compiler::compile(instructions, &mut module);
+    let compiled = crate::compiler::compile_with_options(instructions, &mut module, options);
 
     module.finalize_definitions().unwrap();
 
-    RSCJITModule { module, main_id }
+    RSCJITModule { module, main_id: compiled.main_id, counts_id: compiled.counts_id }
 }
 
-extern "C" fn rsc_init() {
+// Recovers the runtime from the context pointer made by RSCJITModule::run.
+fn runtime<'a>(ctx: *mut c_void) -> &'a mut dyn RscRuntime {
+    unsafe { &mut **(ctx as *mut &mut dyn RscRuntime) }
 }
 
-extern "C" fn rsc_out(number: f64) {
-    println!("{:.2}", number);
+extern "C" fn rsc_init(ctx: *mut c_void) {
+    runtime(ctx).start();
 }
 
-extern "C" fn rsc_rand() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range((-0x10000 as f64)..(0x10000 as f64))
+extern "C" fn rsc_out(ctx: *mut c_void, number: f64) {
+    runtime(ctx).output(number);
 }
 
-extern "C" fn rsc_input() -> f64 {
-    loop {
-        print!("Input: ");
-        io::stdout().flush().unwrap();
+extern "C" fn rsc_rand(ctx: *mut c_void) -> f64 {
+    runtime(ctx).random()
+}
 
-        let mut buffer = String::new();
+extern "C" fn rsc_input(ctx: *mut c_void, _line: i32, value: *mut f64) -> i32 {
+    match runtime(ctx).input() {
+        Ok(input) => {
+            unsafe { *value = input };
+            0
+        }
 
-        match io::stdin().read_line(&mut buffer) {
-            Ok(_) => {
-                let trimed_buffer = buffer.trim();
+        Err(status) => status
+    }
+}
 
-                match trimed_buffer.parse::<f64>() {
-                    Ok(float) => return float,
-                    Err(_) => {}
-                }
-            }
+extern "C" fn rsc_check(ctx: *mut c_void, line: i32) -> i32 {
+    runtime(ctx).check(line as usize)
+}
 
-            Err(_) => {}
-        }
+extern "C" fn rsc_error(ctx: *mut c_void, status: i32, line: i32) {
+    runtime(ctx).error(status, line as usize);
+}
+
+// What both engines, and rsc.c, print when a program stops early.
+pub fn error_message(status: i32, line: usize) -> String {
+    let reason = match status {
+        crate::compiler::STEP_LIMIT_STATUS => "step limit exceeded",
+        crate::compiler::DIVISION_BY_ZERO_STATUS => "division by zero",
+        crate::compiler::NAN_STATUS => "result is not a number",
+        crate::compiler::END_OF_INPUT_STATUS => "end of input",
+        crate::compiler::INVALID_INPUT_STATUS => "too many invalid entries",
+        crate::compiler::INPUT_ERROR_STATUS => "input error",
+        _ => "runtime error"
+    };
+
+    format!("{} at line {}", reason, line)
+}
+
+// How recorded inputs are written, by both engines and rsc.c: the shortest C-style %g that reads
+// back as the same value, so a recording made by one can be replayed by the other unchanged.
+pub fn format_input(value: f64) -> String {
+    (1..=17)
+        .map(|precision| format_g(value, precision))
+        .filter(|formatted| formatted.parse::<f64>() == Ok(value))
+        .min_by_key(|formatted| formatted.len())
+        .unwrap()
+}
+
+// printf's %.{precision}g: scientific notation for very small or large exponents, otherwise fixed,
+// with trailing zeros removed either way.
+fn format_g(value: f64, precision: usize) -> String {
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+
+    let scientific = format!("{:.*e}", precision - 1, value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_zeros(mantissa), sign, exponent.abs())
+    } else {
+        trim_zeros(&format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)).to_string()
+    }
+}
 
-        println!("Invalid entry, try again.")
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
     }
 }
@@ -0,0 +1,114 @@
+use crate::cfg::{self, BasicBlock};
+use crate::parser::{self, Instruction};
+
+// Renders the program's control-flow graph in Graphviz DOT format. Each node is a basic block
+// labeled with its source lines, and each edge out of a conditional branch is labeled with the
+// condition under which it is taken. Blocks ending in STP are drawn with a double border, and
+// edges that leave the program by branching past the last line, or running off the end, go to an
+// exit node.
+//
+// Render with eg. `dot -Tpng prog.dot -o prog.png`.
+pub fn to_dot(source: &str, instructions: &[Instruction]) -> String {
+    let blocks = cfg::basic_blocks(instructions);
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut lines = vec![
+        "digraph rsc {".to_string(),
+        "    node [shape=box, fontname=\"monospace\"];".to_string(),
+    ];
+
+    for (block_idx, block) in blocks.iter().enumerate() {
+        let label: String = (block.start..block.end).map(|idx| {
+            let lineno = instructions[idx].lineno();
+            let text = source_lines.get(lineno - 1).map(|line| line.trim()).unwrap_or("");
+            format!("{}. {}\\l", lineno, escape(text))
+        }).collect();
+
+        let style = if matches!(instructions[block.last()], Instruction::STP(_)) {
+            ", peripheries=2"
+        } else {
+            ""
+        };
+
+        lines.push(format!("    b{} [label=\"{}\"{}];", block_idx, label, style));
+    }
+
+    if blocks.iter().any(|block| block.exits) {
+        lines.push("    exit [shape=oval, peripheries=2];".to_string());
+    }
+
+    for (block_idx, block) in blocks.iter().enumerate() {
+        for (target, label) in edges(instructions, &blocks, block) {
+            let target = match target {
+                Some(target) => format!("b{}", target),
+                None => "exit".to_string()
+            };
+
+            match label {
+                Some(label) => lines.push(format!("    b{} -> {} [label=\"{}\"];", block_idx, target, label)),
+                None => lines.push(format!("    b{} -> {};", block_idx, target))
+            }
+        }
+    }
+
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+// The outgoing edges of a block along with their labels, where None is the exit node. Unlike
+// BasicBlock.succs, a conditional branch always produces two edges, even when both lead to the
+// same place.
+fn edges(instructions: &[Instruction], blocks: &[BasicBlock], block: &BasicBlock) -> Vec<(Option<usize>, Option<&'static str>)> {
+    let last = &instructions[block.last()];
+    let next = cfg::block_containing(blocks, block.end);
+    let target = last.branch_target()
+        .and_then(|location| parser::target_index(instructions, location))
+        .and_then(|idx| cfg::block_containing(blocks, idx));
+
+    match last {
+        Instruction::STP(_) => vec![],
+        Instruction::BRU(_) => vec![(target, None)],
+
+        Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_) => vec![
+            (next, cfg::branch_condition(last, true)),
+            (target, cfg::branch_condition(last, false)),
+        ],
+
+        _ => vec![(next, None)]
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_renders_blocks_and_labeled_edges() {
+        let source = "INP 10\nLDA 10\nBPA 5\nLDC 0\nSTA 11\nOUT 11\nSTP";
+        let result = parser::parse(source);
+        let dot = to_dot(source, &result.instructions);
+
+        assert!(dot.starts_with("digraph rsc {"));
+        assert!(dot.contains("b0 [label=\"1. INP 10\\l2. LDA 10\\l3. BPA 5\\l\"];"));
+        assert!(dot.contains("b2 [label=\"5. STA 11\\l6. OUT 11\\l7. STP\\l\", peripheries=2];"));
+        assert!(dot.contains("b0 -> b1 [label=\"acc <= 0\"];"));
+        assert!(dot.contains("b0 -> b2 [label=\"acc > 0\"];"));
+        assert!(dot.contains("b1 -> b2;"));
+        assert!(!dot.contains("exit"));
+        assert!(dot.ends_with("}"));
+    }
+
+    #[test]
+    fn it_draws_branches_past_the_end_to_an_exit_node() {
+        let source = "INP 10\nLDA 10\nBPA 9\nOUT 10\nSTP";
+        let result = parser::parse(source);
+        let dot = to_dot(source, &result.instructions);
+
+        assert!(dot.contains("    exit [shape=oval, peripheries=2];"));
+        assert!(dot.contains("b0 -> b1 [label=\"acc <= 0\"];"));
+        assert!(dot.contains("b0 -> exit [label=\"acc > 0\"];"));
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::parser::{self, Instruction};
+
+// Executes RSC programs one instruction at a time, without compiling them. This is the reference
+// for how programs behave, and runs anywhere, including platforms the JIT doesn't support. It also
+// makes it easy to watch a program run, eg. to see which way each branch goes.
+
+// What happened when the machine executed an instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // An instruction ran that had no visible effect outside the machine.
+    Executed,
+
+    // OUT printed a value.
+    Output(f64),
+
+    // INP needs a value for the given location. The instruction doesn't complete until the value
+    // is supplied with Machine::input.
+    InputRequest(u32),
+
+    // The program stopped, either at STP or by running off the end.
+    Halted,
+}
+
+pub struct Machine<'a> {
+    instructions: &'a [Instruction],
+    pub accum: f64,
+    pub memory: HashMap<u32, f64>,
+
+    // The index of the next instruction to execute, or None once the program has halted.
+    pub pc: Option<usize>,
+
+    // The number of instructions executed so far.
+    pub steps: u64,
+
+    rng: StdRng,
+}
+
+impl<'a> Machine<'a> {
+    // Creates a machine at the start of the program. Like the compiled code, the accumulator and
+    // uninitialized memory hold random values, which are repeatable if a seed is given.
+    pub fn new(instructions: &'a [Instruction], seed: Option<u64>) -> Self {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy()
+        };
+
+        Machine {
+            instructions,
+            accum: random_value(&mut rng),
+            memory: HashMap::new(),
+            pc: if instructions.len() > 0 { Some(0) } else { None },
+            steps: 0,
+            rng,
+        }
+    }
+
+    pub fn instructions(self: &Self) -> &'a [Instruction] {
+        self.instructions
+    }
+
+    // The instruction that will execute next.
+    pub fn current(self: &Self) -> Option<&'a Instruction> {
+        self.pc.map(|idx| &self.instructions[idx])
+    }
+
+    pub fn is_halted(self: &Self) -> bool {
+        self.pc.is_none()
+    }
+
+    // Reads a memory location, filling it with a random value the first time it's used.
+    pub fn read(self: &mut Self, location: u32) -> f64 {
+        let rng = &mut self.rng;
+        *self.memory.entry(location).or_insert_with(|| random_value(rng))
+    }
+
+    // Executes the next instruction.
+    pub fn step(self: &mut Self) -> Event {
+        let idx = match self.pc {
+            Some(idx) => idx,
+            None => return Event::Halted
+        };
+
+        let instructions = self.instructions;
+        let next = if idx + 1 < instructions.len() { Some(idx + 1) } else { None };
+        let target = |location: u32| parser::target_index(instructions, location);
+        let mut event = Event::Executed;
+
+        match &instructions[idx] {
+            Instruction::LDA(lda) => self.accum = self.read(lda.location),
+            Instruction::LDC(ldc) => self.accum = ldc.value,
+            Instruction::STA(sta) => { self.memory.insert(sta.location, self.accum); },
+
+            // the pc stays put until the input arrives
+            Instruction::INP(inp) => return Event::InputRequest(inp.location),
+
+            Instruction::OUT(out) => {
+                // like the compiled code, OUT also loads the value into the accumulator
+                self.accum = self.read(out.location);
+                event = Event::Output(self.accum);
+            }
+
+            Instruction::ADC(adc) => self.accum += adc.value,
+            Instruction::ADD(add) => self.accum += self.read(add.location),
+            Instruction::SUB(sub) => self.accum -= self.read(sub.location),
+            Instruction::MUL(mul) => self.accum *= self.read(mul.location),
+            Instruction::DIV(div) => self.accum /= self.read(div.location),
+
+            Instruction::BRU(bru) => {
+                self.pc = target(bru.location);
+                self.steps += 1;
+                return self.halted_or(event);
+            }
+
+            Instruction::STP(_) => {
+                self.pc = None;
+                self.steps += 1;
+                return Event::Halted;
+            }
+
+            instr @ (Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_)) => {
+                if branch_taken(instr, self.accum) {
+                    self.pc = target(instr.branch_target().unwrap());
+                    self.steps += 1;
+                    return self.halted_or(event);
+                }
+            }
+        }
+
+        self.pc = next;
+        self.steps += 1;
+        self.halted_or(event)
+    }
+
+    // Completes a pending INP by storing the value and moving on to the next instruction.
+    pub fn input(self: &mut Self, value: f64) {
+        if let (Some(idx), Some(Instruction::INP(inp))) = (self.pc, self.current()) {
+            self.memory.insert(inp.location, value);
+            self.pc = if idx + 1 < self.instructions.len() { Some(idx + 1) } else { None };
+            self.steps += 1;
+        }
+    }
+
+    fn halted_or(self: &Self, event: Event) -> Event {
+        match (self.pc, event) {
+            (None, Event::Executed) => Event::Halted,
+            (_, event) => event
+        }
+    }
+}
+
+// Whether a conditional branch is taken for the given accumulator value. Always true for BRU and
+// false for anything that isn't a branch.
+pub fn branch_taken(instr: &Instruction, accum: f64) -> bool {
+    match instr {
+        Instruction::BRU(_) => true,
+        Instruction::BPA(_) => accum > 0.0,
+        Instruction::BNA(_) => accum < 0.0,
+        Instruction::BZA(_) => accum == 0.0,
+        _ => false
+    }
+}
+
+// Same range as rsc_rand in the JIT runtime.
+fn random_value(rng: &mut StdRng) -> f64 {
+    rng.gen_range((-0x10000 as f64)..(0x10000 as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a program to completion, feeding it the given inputs, and returns what it printed.
+    fn run(program: &str, inputs: &[f64]) -> Vec<f64> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+
+        let mut machine = Machine::new(&result.instructions, Some(0));
+        let mut inputs = inputs.iter();
+        let mut outputs = vec![];
+
+        loop {
+            match machine.step() {
+                Event::Output(value) => outputs.push(value),
+                Event::InputRequest(_) => machine.input(*inputs.next().unwrap()),
+                Event::Halted => return outputs,
+                Event::Executed => {}
+            }
+        }
+    }
+
+    #[test]
+    fn it_computes_arithmetic() {
+        let outputs = run("INP 10\nINP 11\nLDA 10\nADD 11\nADC 1\nSTA 12\nOUT 12\nLDA 10\nMUL 11\nDIV 11\nSTA 12\nOUT 12\nSTP", &[3.0, 4.0]);
+        assert!(outputs == vec![8.0, 3.0]);
+    }
+
+    #[test]
+    fn it_follows_branches() {
+        // counts down from the input
+        let outputs = run("INP 10\nOUT 10\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP", &[3.0]);
+        assert!(outputs == vec![3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn it_waits_for_input() {
+        let result = parser::parse("INP 10\nOUT 10\nSTP");
+        let mut machine = Machine::new(&result.instructions, None);
+
+        assert!(machine.step() == Event::InputRequest(10));
+        assert!(machine.step() == Event::InputRequest(10));
+
+        machine.input(5.0);
+
+        assert!(machine.step() == Event::Output(5.0));
+        assert!(machine.step() == Event::Halted);
+        assert!(machine.is_halted());
+        assert!(machine.steps == 3);
+    }
+
+    #[test]
+    fn it_fills_uninitialized_memory_repeatably() {
+        let result = parser::parse("OUT 10\nOUT 10\nSTP");
+        let mut first = Machine::new(&result.instructions, Some(7));
+        let mut second = Machine::new(&result.instructions, Some(7));
+
+        let value = first.step();
+        assert!(matches!(value, Event::Output(_)));
+        assert!(first.step() == value);
+        assert!(second.step() == value);
+    }
+
+    #[test]
+    fn it_halts_when_running_off_the_end() {
+        let result = parser::parse("LDC 1\nSTA 10");
+        let mut machine = Machine::new(&result.instructions, None);
+
+        assert!(machine.step() == Event::Executed);
+        assert!(machine.step() == Event::Halted);
+    }
+}
@@ -1,6 +1,29 @@
 pub mod parser;
+pub mod cfg;
+pub mod compare;
 pub mod compiler;
+pub mod config;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod decompile;
+pub mod describe;
 pub mod emitter;
+pub mod graph;
+pub mod interpreter;
+pub mod lint;
+pub mod profile;
+pub mod repl;
+pub mod similarity;
+pub mod slice;
+pub mod stats;
+pub mod symbolic;
+pub mod trace;
+pub mod tui;
+pub mod xref;
+
+#[cfg(test)]
+mod test_util;
 
 pub mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
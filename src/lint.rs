@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet};
+use colored::{Color, Colorize};
+use crate::parser::{self, Instruction};
+
+// Lints are heuristics that flag programs which are valid RSC but probably don't do what the
+// author intended. All of them are opt-in: each lint is allowed until -W or rscc.toml sets it to
+// warn, which prints its warnings, or deny, which also stops build and run from going ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    LdcOfLocation,
+    StoreToBranchTarget,
+    OutputUncomputed,
+    ReadAfterStore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintGroup {
+    Pedagogy,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 4] = [
+        Lint::LdcOfLocation,
+        Lint::StoreToBranchTarget,
+        Lint::OutputUncomputed,
+        Lint::ReadAfterStore,
+    ];
+
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            Lint::LdcOfLocation => "ldc-of-location",
+            Lint::StoreToBranchTarget => "store-to-branch-target",
+            Lint::OutputUncomputed => "output-uncomputed",
+            Lint::ReadAfterStore => "read-after-store",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().find(|lint| lint.name() == name).copied()
+    }
+
+    pub fn group(self: &Self) -> LintGroup {
+        match self {
+            Lint::LdcOfLocation => LintGroup::Pedagogy,
+            Lint::StoreToBranchTarget => LintGroup::Pedagogy,
+            Lint::OutputUncomputed => LintGroup::Pedagogy,
+            Lint::ReadAfterStore => LintGroup::Pedagogy,
+        }
+    }
+
+    // A longer explanation of the problem, written for someone who is new to RSC.
+    pub fn explanation(self: &Self) -> &'static str {
+        match self {
+            Lint::LdcOfLocation => {
+                "LDC loads the number itself into the accumulator, while LDA loads the value stored \
+                at that memory location. This number is used as a memory location elsewhere in the \
+                program, so you may have meant LDA."
+            }
+
+            Lint::StoreToBranchTarget => {
+                "Branch instructions jump to instruction numbers, while STA writes to memory \
+                locations. This STA writes to a location that is also used as a branch target, which \
+                usually means memory locations and instruction numbers have been mixed up. Keep your \
+                data in locations well past the end of your program."
+            }
+
+            Lint::OutputUncomputed => {
+                "Nothing in the program ever stores a value into this location with STA or INP, so OUT \
+                will print whatever garbage the location happens to contain when the program starts."
+            }
+
+            Lint::ReadAfterStore => {
+                "The previous instruction just stored the accumulator into this location, so both hold \
+                the same value. ADD will simply double the accumulator and SUB will always produce zero. \
+                You probably meant to use a different location."
+            }
+        }
+    }
+}
+
+impl LintGroup {
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            LintGroup::Pedagogy => "pedagogy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LintGroup> {
+        match name {
+            "pedagogy" => Some(LintGroup::Pedagogy),
+            _ => None
+        }
+    }
+
+    pub fn lints(self: &Self) -> Vec<Lint> {
+        Lint::ALL.iter().filter(|lint| lint.group() == *self).copied().collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None
+        }
+    }
+}
+
+// Tracks the level of every lint. Levels are applied in order, so later settings (eg. from the
+// command line) override earlier ones (eg. from rscc.toml). Lints that are never mentioned are
+// allowed.
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+    levels: HashMap<Lint, Level>,
+}
+
+impl LintLevels {
+    // Sets the level of a lint group or an individual lint.
+    pub fn set(self: &mut Self, name: &str, level: Level) -> Result<(), String> {
+        if let Some(group) = LintGroup::from_name(name) {
+            for lint in group.lints() {
+                self.levels.insert(lint, level);
+            }
+        } else if let Some(lint) = Lint::from_name(name) {
+            self.levels.insert(lint, level);
+        } else {
+            return Err(format!("Unknown lint or lint group '{}'", name));
+        }
+
+        Ok(())
+    }
+
+    pub fn set_all(self: &mut Self, names: &[String], level: Level) -> Result<(), String> {
+        for name in names {
+            self.set(name, level)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn level(self: &Self, lint: Lint) -> Level {
+        *self.levels.get(&lint).unwrap_or(&Level::Allow)
+    }
+
+    pub fn enabled(self: &Self) -> HashSet<Lint> {
+        Lint::ALL.iter().filter(|lint| self.level(**lint) != Level::Allow).copied().collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub lint: Lint,
+    pub lineno: usize,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(lint: Lint, lineno: usize, message: String) -> Self {
+        Warning { lint, lineno, message }
+    }
+
+    pub fn annotate(self: &Self, source: &str) -> String {
+        let (start, end) = parser::line_span(source, self.lineno);
+
+        format!(
+            "{snippet}\n\n{label} {explanation}",
+            snippet = parser::annotate_range_with_color(source, start, end, &self.message, Color::Yellow),
+            label = format!("[{}]", self.lint.name()).yellow(),
+            explanation = self.lint.explanation()
+        )
+    }
+}
+
+pub fn check(instructions: &[Instruction], enabled: &HashSet<Lint>) -> Vec<Warning> {
+    let mut warnings = vec![];
+
+    let written: HashSet<u32> = instructions.iter().filter_map(|instr| {
+        match instr {
+            Instruction::STA(sta) => Some(sta.location),
+            Instruction::INP(inp) => Some(inp.location),
+            _ => None
+        }
+    }).collect();
+
+    let used: HashSet<u32> = instructions.iter().filter_map(|instr| instr.location()).collect();
+
+    let branch_targets: HashSet<u32> = instructions.iter().filter_map(|instr| {
+        instr.branch_target()
+    }).collect();
+
+    let jumped_to: HashSet<usize> = branch_targets.iter().filter_map(|location| {
+        parser::target_index(instructions, *location)
+    }).collect();
+
+    for (idx, instr) in instructions.iter().enumerate() {
+        match instr {
+            Instruction::LDC(ldc) if enabled.contains(&Lint::LdcOfLocation) && ldc.value >= 0.0 && ldc.value.fract() == 0.0
+                && used.contains(&(ldc.value as u32)) => {
+                let location = ldc.value as u32;
+
+                warnings.push(Warning::new(
+                    Lint::LdcOfLocation,
+                    instr.lineno(),
+                    format!("Loads the number {}, which is also a memory location; did you mean LDA {}?", location, location)
+                ));
+            }
+
+            Instruction::STA(sta) => {
+                if enabled.contains(&Lint::StoreToBranchTarget) && branch_targets.contains(&sta.location) {
+                    warnings.push(Warning::new(
+                        Lint::StoreToBranchTarget,
+                        instr.lineno(),
+                        format!("Stores into {}, which is also the target of a branch", sta.location)
+                    ));
+                }
+
+                if let Some(next @ (Instruction::ADD(_) | Instruction::SUB(_))) = instructions.get(idx + 1) {
+                    if enabled.contains(&Lint::ReadAfterStore) && !jumped_to.contains(&(idx + 1)) && next.location() == Some(sta.location) {
+                        warnings.push(Warning::new(
+                            Lint::ReadAfterStore,
+                            next.lineno(),
+                            format!("Reads location {}, which was just stored", sta.location)
+                        ));
+                    }
+                }
+            }
+
+            Instruction::OUT(out) if enabled.contains(&Lint::OutputUncomputed) && !written.contains(&out.location) => {
+                warnings.push(Warning::new(
+                    Lint::OutputUncomputed,
+                    instr.lineno(),
+                    format!("Outputs location {}, which is never assigned a value", out.location)
+                ));
+            }
+
+            _ => ()
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(program: &str) -> Vec<Warning> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+        check(&result.instructions, &LintGroup::Pedagogy.lints().into_iter().collect())
+    }
+
+    #[test]
+    fn it_detects_ldc_of_a_location() {
+        let warnings = lint("INP 10\nLDC 10\nSTA 11\nOUT 11\nSTP");
+
+        assert!(warnings.len() == 1);
+        assert!(warnings[0].lint == Lint::LdcOfLocation);
+        assert!(warnings[0].lineno == 2);
+    }
+
+    #[test]
+    fn it_detects_ldc_of_a_location_that_is_only_read() {
+        for program in ["LDC 12\nSTA 11\nLDA 12\nOUT 11\nSTP", "LDC 12\nSTA 11\nOUT 12\nSTP"] {
+            let warnings: Vec<Warning> = lint(program).into_iter().filter(|warning| warning.lint == Lint::LdcOfLocation).collect();
+
+            assert!(warnings.len() == 1);
+            assert!(warnings[0].lineno == 1);
+        }
+    }
+
+    #[test]
+    fn it_detects_stores_to_branch_targets() {
+        let warnings = lint("INP 10\nLDA 10\nBPA 5\nSTA 5\nOUT 10\nSTP");
+
+        assert!(warnings.len() == 1);
+        assert!(warnings[0].lint == Lint::StoreToBranchTarget);
+        assert!(warnings[0].lineno == 4);
+    }
+
+    #[test]
+    fn it_detects_output_of_uncomputed_locations() {
+        let warnings = lint("LDC 1\nSTA 10\nOUT 11\nSTP");
+
+        assert!(warnings.len() == 1);
+        assert!(warnings[0].lint == Lint::OutputUncomputed);
+        assert!(warnings[0].lineno == 3);
+    }
+
+    #[test]
+    fn it_detects_reads_right_after_stores() {
+        let warnings = lint("INP 10\nLDA 10\nSTA 11\nSUB 11\nSTA 12\nOUT 12\nSTP");
+
+        assert!(warnings.len() == 1);
+        assert!(warnings[0].lint == Lint::ReadAfterStore);
+        assert!(warnings[0].lineno == 4);
+    }
+
+    #[test]
+    fn it_ignores_reads_after_stores_that_are_jumped_to() {
+        let warnings = lint("INP 10\nLDA 10\nSTA 11\nADD 11\nSTA 11\nBPA 4\nOUT 11\nSTP");
+        assert!(warnings.iter().all(|warning| warning.lint != Lint::ReadAfterStore));
+    }
+
+    #[test]
+    fn it_sets_levels_by_group_and_name() {
+        let mut levels = LintLevels::default();
+        assert!(levels.enabled().len() == 0);
+
+        levels.set("pedagogy", Level::Warn).unwrap();
+        assert!(levels.enabled().len() == Lint::ALL.len());
+
+        levels.set("read-after-store", Level::Allow).unwrap();
+        levels.set("output-uncomputed", Level::Deny).unwrap();
+        assert!(levels.enabled().len() == Lint::ALL.len() - 1);
+        assert!(levels.level(Lint::OutputUncomputed) == Level::Deny);
+
+        assert!(levels.set("foo", Level::Warn).is_err());
+    }
+}
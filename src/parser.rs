@@ -1,6 +1,7 @@
 use std::str::FromStr;
-use colored::Colorize;
+use colored::{Color, Colorize};
 
+#[derive(Clone)]
 pub enum Instruction {
     LDA(LDA),
     LDC(LDC),
@@ -59,12 +60,39 @@ impl Instruction {
             Instruction::STP(stp) => stp.opcode(),
         }
     }
+
+    // The memory location this instruction reads from or writes to, if any. Branches refer to
+    // instructions rather than memory, so they return None here (see branch_target).
+    pub fn location(self: &Self) -> Option<u32> {
+        match self {
+            Instruction::LDA(lda) => Some(lda.location),
+            Instruction::STA(sta) => Some(sta.location),
+            Instruction::INP(inp) => Some(inp.location),
+            Instruction::OUT(out) => Some(out.location),
+            Instruction::ADD(add) => Some(add.location),
+            Instruction::SUB(sub) => Some(sub.location),
+            Instruction::MUL(mul) => Some(mul.location),
+            Instruction::DIV(div) => Some(div.location),
+            _ => None
+        }
+    }
+
+    // The location a branch instruction jumps to, if this is a branch.
+    pub fn branch_target(self: &Self) -> Option<u32> {
+        match self {
+            Instruction::BRU(bru) => Some(bru.location),
+            Instruction::BPA(bpa) => Some(bpa.location),
+            Instruction::BNA(bna) => Some(bna.location),
+            Instruction::BZA(bza) => Some(bza.location),
+            _ => None
+        }
+    }
 }
 
 // Load value from location into accumulator.
 //
 // Example: LDA 5 loads the value stored in memory location 5 into the accumulator.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LDA {
     pub location: u32,
     lineno: usize,
@@ -79,7 +107,7 @@ impl LDA {
 // Load constant value into accumulator.
 //
 // Example: LDC 5 loads the literal number 5 into the accumulator.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LDC {
     pub value: f64,
     lineno: usize,
@@ -94,7 +122,7 @@ impl LDC {
 // Store accumulator into location.
 //
 // Example: STA 5 writes the current value inside the accumulator into memory location 5.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct STA {
     pub location: u32,
     lineno: usize,
@@ -110,7 +138,7 @@ impl STA {
 //
 // Example: INP 5 prompts the user for input via the keyboard. The inputted number is then
 // stored in memory location 5.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct INP {
     pub location: u32,
     lineno: usize,
@@ -125,7 +153,7 @@ impl INP {
 // Output value from location onto the screen.
 //
 // Example: OUT 5 causes the value stored at memory location 5 to show up on the terminal screen.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OUT {
     pub location: u32,
     lineno: usize,
@@ -141,7 +169,7 @@ impl OUT {
 //
 // Example: ADC 5 adds the literal number 5 to the existing accumulator value. If the accumulator
 // originally contains 10, after ADC 5 it will contain a value of 15.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ADC {
     pub value: f64,
     lineno: usize,
@@ -158,7 +186,7 @@ impl ADC {
 // Example: ADD 5 adds the value stored at memory location 5 to the existing accumulator value. If
 // the accumulator originally contains 10 and memory location 5 contains a value of 8, after ADD 5
 // the accumulator will contain a value of 18.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ADD {
     pub location: u32,
     lineno: usize,
@@ -175,7 +203,7 @@ impl ADD {
 // Example: SUB 5 subtracts the value stored at memory location 5 from the existing accumulator value.
 // If the accumulator originally contains 10 and memory location 5 contains a value of 8, after SUB 5
 // the accumulator will contain a value of 2.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SUB {
     pub location: u32,
     lineno: usize,
@@ -192,7 +220,7 @@ impl SUB {
 // Example: MUL 5 multiplies the existing accumulator value by the value stored at memory location 5. If
 // the accumulator originally contains 10 and memory location 5 contains a value of 8, after MUL 5 the
 // accumulator will contain a value of 80.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MUL {
     pub location: u32,
     lineno: usize,
@@ -209,7 +237,7 @@ impl MUL {
 // Example: DIV 5 divides the existing accumulator value by the value stored at memory location 5. If the
 // accumulator originally contains 20 and memory location 5 contains a value of 4, after DIV 5 the
 // accumulator will contain a value of 5.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DIV {
     pub location: u32,
     lineno: usize,
@@ -226,7 +254,7 @@ impl DIV {
 // Example: BRU 5 causes execution to jump to instruction 5, skipping all the instructions between 5 and the
 // current instruction. Note that it is perfectly acceptable to jump backwards as well as forwards, i.e. to
 // an instruction before or after the current one.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BRU {
     pub location: u32,
     lineno: usize,
@@ -245,7 +273,7 @@ impl BRU {
 // from instruction 5. If the accumulator contains a value of 0 or less, after BPA 5 execution will continue
 // with the instruction immediately following the current one. Note that it is perfectly acceptable to jump
 // backwards as well as forwards, i.e. to an instruction before or after the current one.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BPA {
     pub location: u32,
     lineno: usize,
@@ -264,7 +292,7 @@ impl BPA {
 // instruction 5. If the accumulator contains a value of 0 or greater, after BNA 5 execution will continue with
 // the instruction immediately following the current one. Note that it is perfectly acceptable to jump backwards
 // as well as forwards, i.e. to an instruction before or after the current one.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BNA {
     pub location: u32,
     lineno: usize,
@@ -283,7 +311,7 @@ impl BNA {
 // other positive or negative value, after BZA 5 execution will continue with the instruction immediately following
 // the current one. Note that it is perfectly acceptable to jump backwards as well as forwards, i.e. to an
 // instruction before or after the current one.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BZA {
     pub location: u32,
     lineno: usize,
@@ -298,7 +326,7 @@ impl BZA {
 // Stop execution.
 //
 // Example: STP terminates your program. All programs must have STP as the last instruction.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct STP {
     lineno: usize,
 }
@@ -309,7 +337,7 @@ impl STP {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[derive(PartialEq)]
 pub enum DiagnosticType {
     InvalidOpcode,
@@ -317,6 +345,7 @@ pub enum DiagnosticType {
     MissingOperand,
     TooManyOperands,
     MissingStp,
+    LocationOutOfRange,
 }
 
 #[derive(Debug)]
@@ -326,23 +355,27 @@ pub struct Diagnostic {
     pub end: usize,
 }
 
-fn last_n<'a>(n: usize, list: &Vec<&'a str>) -> Vec<&'a str> {
+fn last_n<'a>(n: usize, list: &[&'a str]) -> Vec<&'a str> {
     if list.len() > n {
         list[(list.len() - n)..].to_vec()
     } else {
-        list.clone()
+        list.to_vec()
     }
 }
 
-fn first_n<'a>(n: usize, list: &Vec<&'a str>) -> Vec<&'a str> {
+fn first_n<'a>(n: usize, list: &[&'a str]) -> Vec<&'a str> {
     if list.len() > n {
         list[0..n].to_vec()
     } else {
-        list.clone()
+        list.to_vec()
     }
 }
 
-fn annotate_range(source: &str, start: usize, end: usize, message: &str) -> String {
+pub fn annotate_range(source: &str, start: usize, end: usize, message: &str) -> String {
+    annotate_range_with_color(source, start, end, message, Color::Red)
+}
+
+pub fn annotate_range_with_color(source: &str, start: usize, end: usize, message: &str, color: Color) -> String {
     let bol = match source[0..start].rfind("\n") {
         Some(pos) => (pos + 1).clamp(0, source.len() - 1),
         None => 0
@@ -363,7 +396,7 @@ fn annotate_range(source: &str, start: usize, end: usize, message: &str) -> Stri
         let colored_line = if line.starts_with("#") {
             format!("{}", line.green())
         } else {
-            format!("{}", line)
+            line.to_string()
         };
 
         let result = format!("{} {}", format!("{}.", lineno).blue(), colored_line);
@@ -391,13 +424,37 @@ fn annotate_range(source: &str, start: usize, end: usize, message: &str) -> Stri
         line = &source[bol..eol],
         lineno = format!("{}.", current_lineno).blue(),
         leading_ws = " ".repeat(start - bol + format!("{}", lineno).len() + 2),
-        message = format!("^{dashes} {message}", dashes = "-".repeat((end - start).saturating_sub(1)), message = message).red(),
+        message = format!("^{dashes} {message}", dashes = "-".repeat((end - start).saturating_sub(1)), message = message).color(color),
         after = after_lines_keep_linenos.join("\n")
     );
 
     annotated.trim().to_string()
 }
 
+// Branches name the line to jump to. Execution resumes at the first instruction on or after that
+// line, which is how the compiler lays out branches. Returns the index of that instruction, or
+// None if the target lies past the end of the program.
+pub fn target_index(instructions: &[Instruction], location: u32) -> Option<usize> {
+    instructions.iter().position(|instr| instr.lineno() >= location as usize)
+}
+
+// Returns the start and end positions of the trimmed contents of the given (1-based) line. Useful
+// for annotating instructions, which only know their line number.
+pub fn line_span(source: &str, lineno: usize) -> (usize, usize) {
+    let mut pos = 0;
+
+    for (idx, line) in source.split('\n').enumerate() {
+        if idx + 1 == lineno {
+            let start = pos + (line.len() - line.trim_start().len());
+            return (start, start + line.trim().len());
+        }
+
+        pos += line.len() + 1;
+    }
+
+    (source.len(), source.len())
+}
+
 impl Diagnostic {
     pub fn new(ty: DiagnosticType, start: usize, end: usize) -> Self {
         Diagnostic { ty, start, end }
@@ -424,6 +481,10 @@ impl Diagnostic {
             DiagnosticType::MissingStp => {
                 annotate_range(source, self.start, self.end, "Program must contain at least one STP instruction")
             }
+
+            DiagnosticType::LocationOutOfRange => {
+                annotate_range(source, self.start, self.end, "Memory location is outside the configured memory size")
+            }
         }
     }
 }
@@ -440,7 +501,25 @@ impl ParseResult {
     }
 }
 
+// Options for the variations of the RSC language accepted by different courses. The defaults match
+// the original RSC interpreter.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    // Allow comments after an instruction, eg. "LDC 5  # load 5".
+    pub inline_comments: bool,
+
+    // Accept opcodes in any case, eg. "ldc 5".
+    pub case_insensitive: bool,
+
+    // Reject memory locations greater than or equal to this number.
+    pub memory_size: Option<u32>,
+}
+
 pub fn parse(str: &str) -> ParseResult {
+    parse_with_options(str, &ParseOptions::default())
+}
+
+pub fn parse_with_options(str: &str, options: &ParseOptions) -> ParseResult {
     let raw_lines = str.split('\n');
     let lines = raw_lines.map(|line| line.trim());
     let mut instructions: Vec<Instruction> = vec![];
@@ -448,12 +527,21 @@ pub fn parse(str: &str) -> ParseResult {
     let mut cur_pos: usize = 0;
     let mut found_stp = false;
 
-    for (mut lineno, line) in lines.enumerate() {
+    for (mut lineno, raw_line) in lines.enumerate() {
         lineno += 1;
 
+        let line = if options.inline_comments {
+            match raw_line.find('#') {
+                Some(pos) => raw_line[0..pos].trim_end(),
+                None => raw_line
+            }
+        } else {
+            raw_line
+        };
+
         // skip comments
         if line.len() == 0 || line.starts_with('#') {
-            cur_pos += line.len() + 1;
+            cur_pos += raw_line.len() + 1;
             continue;
         }
 
@@ -477,36 +565,40 @@ pub fn parse(str: &str) -> ParseResult {
             }
         }
 
-        match opcode {
-            Some("STP") => {
-                if operands.len() > 0 {
-                    let first_op_start = operands[0].1;
+        let upcased_opcode = opcode
+            .filter(|_| options.case_insensitive)
+            .map(|opcode| opcode.to_ascii_uppercase());
 
-                    diagnostics.push(
-                        Diagnostic::new(
-                            DiagnosticType::TooManyOperands,
-                            first_op_start,
-                            cur_pos + line.len()
-                        )
+        if let Some(upcased) = &upcased_opcode {
+            opcode = Some(upcased.as_str());
+        }
+
+        match opcode {
+            Some("STP") if operands.len() > 0 => {
+                let first_op_start = operands[0].1;
+
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticType::TooManyOperands,
+                        first_op_start,
+                        cur_pos + line.len()
                     )
-                }
+                )
             }
 
-            Some(_) => {
-                if operands.len() > 1 {
-                    let first_extra_op_start = operands[1].1;
+            Some(opcode) if opcode != "STP" && operands.len() > 1 => {
+                let first_extra_op_start = operands[1].1;
 
-                    diagnostics.push(
-                        Diagnostic::new(
-                            DiagnosticType::TooManyOperands,
-                            first_extra_op_start,
-                            cur_pos + line.len()
-                        )
+                diagnostics.push(
+                    Diagnostic::new(
+                        DiagnosticType::TooManyOperands,
+                        first_extra_op_start,
+                        cur_pos + line.len()
                     )
-                }
+                )
             },
 
-            None => {}
+            _ => {}
         }
 
         let operand = if operands.len() > 0 {
@@ -515,6 +607,8 @@ pub fn parse(str: &str) -> ParseResult {
             None
         };
 
+        let instruction_count = instructions.len();
+
         match opcode {
             Some("LDA") => match parse_operand::<u32>(operand) {
                 Some(Ok(operand)) => instructions.push(
@@ -698,7 +792,17 @@ pub fn parse(str: &str) -> ParseResult {
             )
         }
 
-        cur_pos += line.len() + 1;
+        if let (Some(memory_size), Some(instr), Some((op, start))) = (options.memory_size, instructions.get(instruction_count), operand) {
+            match instr.location() {
+                Some(location) if location >= memory_size => diagnostics.push(
+                    Diagnostic::new(DiagnosticType::LocationOutOfRange, start, start + op.len())
+                ),
+
+                _ => ()
+            }
+        }
+
+        cur_pos += raw_line.len() + 1;
     }
 
     if !found_stp {
@@ -711,7 +815,7 @@ pub fn parse(str: &str) -> ParseResult {
         )
     }
 
-    return ParseResult::new(instructions, diagnostics, str.to_string());
+    ParseResult::new(instructions, diagnostics, str.to_string())
 }
 
 fn parse_operand<T: FromStr>(operand: Option<(&str, usize)>) -> Option<Result<T, Diagnostic>> {
@@ -753,27 +857,27 @@ mod tests {
 
         match &result.instructions[0] {
             Instruction::LDC(ldc) => assert!(ldc.value == 5.0),
-            _ => assert!(false, "Expected LDC on line 1")
+            _ => panic!("Expected LDC on line 1")
         }
 
         match &result.instructions[1] {
             Instruction::INP(inp) => assert!(inp.location == 10),
-            _ => assert!(false, "Expected INP on line 2")
+            _ => panic!("Expected INP on line 2")
         }
 
         match &result.instructions[2] {
             Instruction::STA(sta) => assert!(sta.location == 11),
-            _ => assert!(false, "Expected LDC on line 3")
+            _ => panic!("Expected LDC on line 3")
         }
 
         match &result.instructions[3] {
             Instruction::OUT(out) => assert!(out.location == 11),
-            _ => assert!(false, "Expected OUT on line 4")
+            _ => panic!("Expected OUT on line 4")
         }
 
         match &result.instructions[4] {
-            Instruction::STP(_) => assert!(true),
-            _ => assert!(false, "Expected STP on line 5")
+            Instruction::STP(_) => {},
+            _ => panic!("Expected STP on line 5")
         }
     }
 
@@ -791,27 +895,27 @@ mod tests {
 
         match &result.instructions[0] {
             Instruction::BRU(bru) => assert!(bru.location == 5),
-            _ => assert!(false, "Expected BRU on line 1")
+            _ => panic!("Expected BRU on line 1")
         }
 
         match &result.instructions[1] {
             Instruction::BPA(bpa) => assert!(bpa.location == 10),
-            _ => assert!(false, "Expected BPA on line 2")
+            _ => panic!("Expected BPA on line 2")
         }
 
         match &result.instructions[2] {
             Instruction::BNA(bna) => assert!(bna.location == 15),
-            _ => assert!(false, "Expected BNA on line 3")
+            _ => panic!("Expected BNA on line 3")
         }
 
         match &result.instructions[3] {
             Instruction::BZA(bza) => assert!(bza.location == 20),
-            _ => assert!(false, "Expected BZA on line 4")
+            _ => panic!("Expected BZA on line 4")
         }
 
         match &result.instructions[4] {
-            Instruction::STP(_) => assert!(true),
-            _ => assert!(false, "Expected STP on line 5")
+            Instruction::STP(_) => {},
+            _ => panic!("Expected STP on line 5")
         }
     }
 
@@ -830,32 +934,32 @@ mod tests {
 
         match &result.instructions[0] {
             Instruction::ADC(adc) => assert!(adc.value == 5.0),
-            _ => assert!(false, "Expected ADC on line 1")
+            _ => panic!("Expected ADC on line 1")
         }
 
         match &result.instructions[1] {
             Instruction::ADD(add) => assert!(add.location == 10),
-            _ => assert!(false, "Expected ADD on line 2")
+            _ => panic!("Expected ADD on line 2")
         }
 
         match &result.instructions[2] {
             Instruction::SUB(sub) => assert!(sub.location == 15),
-            _ => assert!(false, "Expected SUB on line 3")
+            _ => panic!("Expected SUB on line 3")
         }
 
         match &result.instructions[3] {
             Instruction::MUL(mul) => assert!(mul.location == 20),
-            _ => assert!(false, "Expected MUL on line 4")
+            _ => panic!("Expected MUL on line 4")
         }
 
         match &result.instructions[4] {
             Instruction::DIV(div) => assert!(div.location == 25),
-            _ => assert!(false, "Expected DIV on line 5")
+            _ => panic!("Expected DIV on line 5")
         }
 
         match &result.instructions[5] {
-            Instruction::STP(_) => assert!(true),
-            _ => assert!(false, "Expected STP on line 6")
+            Instruction::STP(_) => {},
+            _ => panic!("Expected STP on line 6")
         }
     }
 
@@ -907,6 +1011,40 @@ mod tests {
         assert!(diagnostic.end == 3);
     }
 
+    #[test]
+    fn it_allows_inline_comments_when_configured() {
+        let options = ParseOptions { inline_comments: true, ..ParseOptions::default() };
+        let result = parse_with_options("LDC 5  # load five\nSTP # done", &options);
+
+        assert!(result.diagnostics.len() == 0);
+        assert!(result.instructions.len() == 2);
+
+        let result = parse("LDC 5  # load five\nSTP");
+        assert!(result.diagnostics[0].ty == DiagnosticType::TooManyOperands);
+    }
+
+    #[test]
+    fn it_allows_lowercase_opcodes_when_configured() {
+        let options = ParseOptions { case_insensitive: true, ..ParseOptions::default() };
+        let result = parse_with_options("ldc 5\nSta 10\nstp", &options);
+
+        assert!(result.diagnostics.len() == 0);
+        assert!(result.instructions.len() == 3);
+    }
+
+    #[test]
+    fn it_detects_locations_outside_of_memory() {
+        let options = ParseOptions { memory_size: Some(100), ..ParseOptions::default() };
+        let result = parse_with_options("LDC 500\nSTA 100\nSTP", &options);
+
+        assert!(result.diagnostics.len() == 1);
+
+        let diagnostic = &result.diagnostics[0];
+        assert!(diagnostic.ty == DiagnosticType::LocationOutOfRange);
+        assert!(diagnostic.start == 12);
+        assert!(diagnostic.end == 15);
+    }
+
     #[test]
     fn it_detects_too_many_operands() {
         let result = parse("LDC 5 5\nSTP");
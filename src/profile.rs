@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use crate::parser::Instruction;
+use crate::trace;
+
+// Execution profiles, for seeing why a loop is slow or never runs. A profile is just a count of how
+// many times each instruction executed, which both engines can collect: the interpreter counts as
+// it goes and the JIT compiles in a counter for each instruction. Memory reads and writes follow
+// from the counts, since every instruction touches the same location each time it runs.
+
+// The number of lines listed as hot spots.
+pub const HOT_SPOTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Access {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+pub struct Profile<'a> {
+    instructions: &'a [Instruction],
+
+    // Indexed like the instructions.
+    pub counts: Vec<u64>,
+}
+
+impl<'a> Profile<'a> {
+    pub fn new(instructions: &'a [Instruction]) -> Self {
+        Profile { instructions, counts: vec![0; instructions.len()] }
+    }
+
+    pub fn from_counts(instructions: &'a [Instruction], mut counts: Vec<u64>) -> Self {
+        counts.resize(instructions.len(), 0);
+        Profile { instructions, counts }
+    }
+
+    pub fn record(self: &mut Self, idx: usize) {
+        self.counts[idx] += 1;
+    }
+
+    pub fn total(self: &Self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn accesses(self: &Self) -> BTreeMap<u32, Access> {
+        let mut accesses: BTreeMap<u32, Access> = BTreeMap::new();
+
+        for (instr, count) in self.instructions.iter().zip(&self.counts) {
+            let location = match instr.location() {
+                Some(location) => location,
+                None => continue
+            };
+
+            let access = accesses.entry(location).or_default();
+
+            match instr {
+                Instruction::STA(_) | Instruction::INP(_) => access.writes += count,
+                _ => access.reads += count
+            }
+        }
+
+        accesses
+    }
+
+    // The most executed instructions, most first, leaving out any that never ran.
+    pub fn hot_spots(self: &Self, n: usize) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.counts.len()).filter(|idx| self.counts[*idx] > 0).collect();
+        indices.sort_by(|a, b| self.counts[*b].cmp(&self.counts[*a]).then(a.cmp(b)));
+        indices.truncate(n);
+        indices
+    }
+}
+
+// The source with each instruction's count in the margin, followed by the hot spots, the memory
+// accesses and the total.
+pub fn render(source: &str, profile: &Profile) -> String {
+    let instructions = profile.instructions;
+    let total = profile.total();
+    let mut lines = vec![format!("{:>10}  source", "count")];
+
+    let mut counts_by_line: BTreeMap<usize, u64> = BTreeMap::new();
+
+    for (instr, count) in instructions.iter().zip(&profile.counts) {
+        counts_by_line.insert(instr.lineno(), *count);
+    }
+
+    for (idx, line) in source.lines().enumerate() {
+        let margin = match counts_by_line.get(&(idx + 1)) {
+            Some(count) => count.to_string(),
+            None => "".to_string()
+        };
+
+        lines.push(format!("{:>10}  {}. {}", margin, idx + 1, line));
+    }
+
+    let hot_spots = profile.hot_spots(HOT_SPOTS);
+
+    if hot_spots.len() > 0 {
+        lines.push("".to_string());
+        lines.push("Hot spots:".to_string());
+
+        for idx in hot_spots {
+            let instr = &instructions[idx];
+            let share = profile.counts[idx] as f64 / total as f64 * 100.0;
+
+            lines.push(format!(
+                "  line {:<4} {:<12} {:>10} ({:.1}%)",
+                instr.lineno(), trace::instruction_text(instr), profile.counts[idx], share
+            ));
+        }
+    }
+
+    let never: Vec<String> = instructions.iter().zip(&profile.counts)
+        .filter(|(_, count)| **count == 0)
+        .map(|(instr, _)| instr.lineno().to_string())
+        .collect();
+
+    if never.len() > 0 {
+        lines.push("".to_string());
+        lines.push(format!("Never executed: line(s) {}", never.join(", ")));
+    }
+
+    let accesses = profile.accesses();
+
+    if accesses.len() > 0 {
+        lines.push("".to_string());
+        lines.push("Memory:".to_string());
+
+        for (location, access) in accesses {
+            lines.push(format!("  {:<8} {:>10} read(s) {:>10} write(s)", format!("m[{}]", location), access.reads, access.writes));
+        }
+    }
+
+    lines.push("".to_string());
+    lines.push(format!("Total instructions executed: {}", total));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Event, Machine};
+    use crate::parser;
+
+    // counts down from 3, printing each number
+    const COUNTDOWN: &str = "LDC 3\nSTA 10\nOUT 10\nLDA 10\nADC -1\nSTA 10\nBPA 3\nSTP\nOUT 11";
+
+    #[test]
+    fn it_counts_instructions_and_accesses() {
+        let result = parser::parse(COUNTDOWN);
+        let mut machine = Machine::new(&result.instructions, Some(0));
+        let mut profile = Profile::new(&result.instructions);
+
+        while let Some(idx) = machine.pc {
+            profile.record(idx);
+
+            if machine.step() == Event::Halted {
+                break;
+            }
+        }
+
+        assert!(profile.counts == vec![1, 1, 3, 3, 3, 3, 3, 1, 0]);
+        assert!(profile.total() == 18);
+        assert!(profile.accesses()[&10] == Access { reads: 6, writes: 4 });
+        assert!(profile.hot_spots(2) == vec![2, 3]);
+
+        let report = render(COUNTDOWN, &profile);
+        assert!(report.contains("         3  3. OUT 10"));
+        assert!(report.contains("Never executed: line(s) 9"));
+        assert!(report.ends_with("Total instructions executed: 18"));
+    }
+
+    #[test]
+    fn it_reads_counts_from_the_jit() {
+        struct Quiet;
+
+        impl crate::emitter::RscRuntime for Quiet {
+            fn output(self: &mut Self, _: f64) {}
+            fn input(self: &mut Self) -> Result<f64, i32> { Ok(0.0) }
+            fn random(self: &mut Self) -> f64 { 0.0 }
+        }
+
+        let result = parser::parse(COUNTDOWN);
+        let options = crate::compiler::CompileOptions { profile: true, ..Default::default() };
+        let rsc_module = crate::emitter::emit_jit_module_with_options(result.instructions.clone(), &options);
+
+        rsc_module.run(&mut Quiet);
+
+        assert!(rsc_module.counts() == Some(vec![1, 1, 3, 3, 3, 3, 3, 1, 0]));
+    }
+}
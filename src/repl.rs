@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use crate::interpreter::{Event, Machine};
+use crate::parser::{self, DiagnosticType, Instruction, ParseOptions};
+use crate::trace;
+
+// An interactive prompt that runs each instruction as soon as it's entered, against an accumulator
+// and memory that persist between lines. Entered lines build up a program, numbered from 1 like a
+// file, so a branch jumps back to an earlier line and runs forward from there to the newest one.
+// Labels can stand in for line numbers, eg. ":label loop" followed later by "BPA loop".
+
+pub const HELP: &str = "\
+Enter an instruction to run it, eg. LDC 5 or ADD 10. Meta-commands:
+  :acc                 show the accumulator
+  :mem [location]      show memory, or a single location
+  :label <name> [line] name the next line, or the given one, for use in branches
+  :labels              list labels
+  :list                show the instructions entered so far
+  :load <file>         replace the entered instructions with a file and run it
+  :reset               forget everything and start again
+  :help                show this message
+  :quit                leave the REPL";
+
+// Runs that execute more instructions than this are assumed to be stuck in a loop.
+pub const MAX_STEPS: u64 = 10_000;
+
+pub struct Repl {
+    options: ParseOptions,
+    seed: Option<u64>,
+    precision: usize,
+
+    // Everything entered so far, one instruction per line.
+    source: String,
+    instructions: Vec<Instruction>,
+    labels: BTreeMap<String, usize>,
+    pub accum: f64,
+    pub memory: HashMap<u32, f64>,
+
+    // Counts runs, so that each one fills uninitialized memory with different random values even
+    // when seeded.
+    runs: u64,
+}
+
+impl Repl {
+    pub fn new(options: ParseOptions, seed: Option<u64>, precision: usize) -> Self {
+        Repl {
+            options,
+            seed,
+            precision,
+            source: String::new(),
+            instructions: vec![],
+            labels: BTreeMap::new(),
+            accum: Machine::new(&[], seed).accum,
+            memory: HashMap::new(),
+            runs: 0,
+        }
+    }
+
+    // The line number the next instruction entered will have.
+    pub fn next_line(self: &Self) -> usize {
+        if self.source.len() == 0 {
+            1
+        } else {
+            self.source.split('\n').count() + 1
+        }
+    }
+
+    pub fn reset(self: &mut Self) {
+        *self = Repl::new(self.options.clone(), self.seed, self.precision);
+    }
+
+    // Runs a line of input, either an instruction or a meta-command, and returns what to print.
+    // INP instructions get their values from read_input, which returns None if there are no more.
+    pub fn eval(self: &mut Self, line: &str, read_input: &mut dyn FnMut(u32) -> Option<f64>) -> Result<String, String> {
+        let line = line.trim();
+
+        match line.strip_prefix(':') {
+            Some(command) => self.meta(command, read_input),
+            None if line.len() == 0 => Ok("".to_string()),
+            None => self.instruction(line, read_input)
+        }
+    }
+
+    fn instruction(self: &mut Self, line: &str, read_input: &mut dyn FnMut(u32) -> Option<f64>) -> Result<String, String> {
+        // swap a label for its line number
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let line = match words.as_slice() {
+            [opcode, operand] => match self.labels.get(*operand) {
+                Some(lineno) => format!("{} {}", opcode, lineno),
+                None => line.to_string()
+            },
+
+            _ => line.to_string()
+        };
+
+        // a single instruction on its own is never a complete program, so a missing STP is fine
+        let mut result = parser::parse_with_options(&line, &self.options);
+
+        // errors are shown after the lines entered so far, so they have the right line number
+        let (context, offset) = match self.source.len() {
+            0 => (line.clone(), 0),
+            len => (format!("{}\n{}", self.source, line), len + 1)
+        };
+
+        let errors: Vec<String> = result.diagnostics.iter_mut()
+            .filter(|diagnostic| diagnostic.ty != DiagnosticType::MissingStp)
+            .map(|diagnostic| {
+                diagnostic.start += offset;
+                diagnostic.end += offset;
+                diagnostic.annotate(&context)
+            })
+            .collect();
+
+        if errors.len() > 0 {
+            return Err(errors.join("\n"));
+        }
+
+        if result.instructions.len() == 0 {
+            return Ok("".to_string());
+        }
+
+        if self.source.len() > 0 {
+            self.source.push('\n');
+        }
+
+        self.source += &line;
+        self.instructions = parser::parse_with_options(&self.source, &self.options).instructions;
+
+        let idx = self.instructions.len() - 1;
+        let mut lines = self.run(idx, read_input)?;
+
+        let location = self.instructions[idx].location();
+        let mut state = format!("acc = {}", self.accum);
+
+        if let Some((location, value)) = location.and_then(|location| self.memory.get(&location).map(|value| (location, value))) {
+            state += &format!(", m[{}] = {}", location, value);
+        }
+
+        lines.push(state);
+        Ok(lines.join("\n"))
+    }
+
+    // Runs the entered instructions from idx until the program runs off the end or stops, and
+    // returns the values printed along the way.
+    fn run(self: &mut Self, idx: usize, read_input: &mut dyn FnMut(u32) -> Option<f64>) -> Result<Vec<String>, String> {
+        self.runs += 1;
+
+        let mut machine = Machine::new(&self.instructions, self.seed.map(|seed| seed + self.runs));
+        machine.accum = self.accum;
+        machine.memory = std::mem::take(&mut self.memory);
+        machine.pc = Some(idx);
+
+        let mut outputs = vec![];
+        let mut error = None;
+
+        while !machine.is_halted() {
+            if machine.steps == MAX_STEPS {
+                let line = machine.current().unwrap().lineno();
+                error = Some(format!("Stopped at line {} after {} steps; is there a loop?", line, MAX_STEPS));
+                break;
+            }
+
+            match machine.step() {
+                Event::Output(value) => outputs.push(format!("{:.*}", self.precision, value)),
+                Event::Executed | Event::Halted => {}
+
+                Event::InputRequest(location) => match read_input(location) {
+                    Some(value) => machine.input(value),
+                    None => {
+                        error = Some(format!("No input for m[{}]", location));
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.accum = machine.accum;
+        self.memory = machine.memory;
+
+        match error {
+            Some(message) => Err(outputs.into_iter().chain([message]).collect::<Vec<String>>().join("\n")),
+            None => Ok(outputs)
+        }
+    }
+
+    fn meta(self: &mut Self, command: &str, read_input: &mut dyn FnMut(u32) -> Option<f64>) -> Result<String, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["acc"] => Ok(format!("acc = {}", self.accum)),
+
+            ["mem"] => {
+                let memory: BTreeMap<&u32, &f64> = self.memory.iter().collect();
+
+                if memory.len() == 0 {
+                    return Ok("No memory locations have been used".to_string());
+                }
+
+                Ok(memory.iter().map(|(location, value)| format!("m[{}] = {}", location, value)).collect::<Vec<String>>().join("\n"))
+            }
+
+            ["mem", location] => {
+                let location = location.trim_start_matches("m[").trim_end_matches(']');
+                let location = location.parse::<u32>().map_err(|_| format!("'{}' is not a memory location", location))?;
+
+                match self.memory.get(&location) {
+                    Some(value) => Ok(format!("m[{}] = {}", location, value)),
+                    None => Ok(format!("m[{}] is uninitialized", location))
+                }
+            }
+
+            ["label", name] => self.label(name, self.next_line()),
+
+            ["label", name, line] => match line.parse::<usize>() {
+                Ok(line) => self.label(name, line),
+                Err(_) => Err(format!("'{}' is not a line number", line))
+            },
+
+            ["labels"] => {
+                if self.labels.len() == 0 {
+                    return Ok("No labels have been defined".to_string());
+                }
+
+                Ok(self.labels.iter().map(|(name, line)| format!("{} = line {}", name, line)).collect::<Vec<String>>().join("\n"))
+            }
+
+            ["list"] => {
+                if self.instructions.len() == 0 {
+                    return Ok("No instructions have been entered".to_string());
+                }
+
+                Ok(self.instructions.iter()
+                    .map(|instr| format!("{}. {}", instr.lineno(), trace::instruction_text(instr)))
+                    .collect::<Vec<String>>()
+                    .join("\n"))
+            }
+
+            ["load", file] => self.load(file, read_input),
+
+            ["reset"] => {
+                self.reset();
+                Ok("Reset the accumulator, memory and instructions".to_string())
+            }
+
+            ["help"] => Ok(HELP.to_string()),
+            _ => Err(format!("Unknown command ':{}'; try :help", command))
+        }
+    }
+
+    fn label(self: &mut Self, name: &str, line: usize) -> Result<String, String> {
+        if name.parse::<f64>().is_ok() {
+            return Err(format!("'{}' is a number, so it can't be a label", name));
+        }
+
+        self.labels.insert(name.to_string(), line);
+        Ok(format!("{} = line {}", name, line))
+    }
+
+    // Replaces the entered instructions with the contents of a file, then runs it from the start
+    // against the current accumulator and memory.
+    fn load(self: &mut Self, file: &str, read_input: &mut dyn FnMut(u32) -> Option<f64>) -> Result<String, String> {
+        let contents = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        let source = contents.trim_end().to_string();
+        let result = parser::parse_with_options(&source, &self.options);
+
+        let errors: Vec<String> = result.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.ty != DiagnosticType::MissingStp)
+            .map(|diagnostic| diagnostic.annotate(&source))
+            .collect();
+
+        if errors.len() > 0 {
+            return Err(errors.join("\n"));
+        }
+
+        self.source = source;
+        self.instructions = result.instructions;
+
+        let mut lines = vec![format!("Loaded {} instruction(s) from {}", self.instructions.len(), file)];
+
+        if self.instructions.len() > 0 {
+            lines.extend(self.run(0, read_input)?);
+        }
+
+        lines.push(format!("acc = {}", self.accum));
+        Ok(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_input(_: u32) -> Option<f64> {
+        None
+    }
+
+    #[test]
+    fn it_keeps_state_between_lines() {
+        let mut repl = Repl::new(ParseOptions::default(), Some(0), 2);
+
+        assert!(repl.eval("LDC 5", &mut no_input) == Ok("acc = 5".to_string()));
+        assert!(repl.eval("STA 10", &mut no_input) == Ok("acc = 5, m[10] = 5".to_string()));
+        assert!(repl.eval("ADD 10", &mut no_input) == Ok("acc = 10, m[10] = 5".to_string()));
+        assert!(repl.eval("INP 11", &mut |_| Some(2.5)) == Ok("acc = 10, m[11] = 2.5".to_string()));
+        assert!(repl.eval("OUT 11", &mut no_input) == Ok("2.50\nacc = 2.5, m[11] = 2.5".to_string()));
+        assert!(repl.eval(":mem", &mut no_input) == Ok("m[10] = 5\nm[11] = 2.5".to_string()));
+        assert!(repl.next_line() == 6);
+    }
+
+    #[test]
+    fn it_branches_to_labels() {
+        let mut repl = Repl::new(ParseOptions::default(), Some(0), 2);
+
+        // counts down from 3, printing each number
+        repl.eval("LDC 3", &mut no_input).unwrap();
+        repl.eval("STA 10", &mut no_input).unwrap();
+        repl.eval(":label loop", &mut no_input).unwrap();
+        repl.eval("OUT 10", &mut no_input).unwrap();
+        repl.eval("ADC -1", &mut no_input).unwrap();
+        repl.eval("STA 10", &mut no_input).unwrap();
+
+        assert!(repl.eval("BPA loop", &mut no_input) == Ok("2.00\n1.00\nacc = 0".to_string()));
+        assert!(repl.eval(":list", &mut no_input).unwrap().ends_with("6. BPA 3"));
+    }
+
+    #[test]
+    fn it_rejects_invalid_instructions() {
+        let mut repl = Repl::new(ParseOptions::default(), Some(0), 2);
+
+        assert!(repl.eval("LDC", &mut no_input).unwrap_err().contains("Missing operand"));
+        assert!(repl.eval("LDC 1", &mut no_input).is_ok());
+        assert!(repl.eval("BRU 1", &mut no_input).unwrap_err().contains("after 10000 steps"));
+
+        repl.eval(":reset", &mut no_input).unwrap();
+        assert!(repl.next_line() == 1);
+        assert!(repl.eval(":frobnicate", &mut no_input).is_err());
+    }
+}
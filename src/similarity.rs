@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use crate::parser::{self, Instruction};
+
+// Compares submissions for academic-integrity reviews. Programs are first normalized so that
+// cosmetic changes don't hide copying, then scored by how many short runs of instructions they
+// have in common.
+
+pub const DEFAULT_NGRAM: usize = 4;
+
+// Turns a program into a list of tokens, one per instruction, that don't depend on comments,
+// whitespace, line numbers or the choice of memory locations. Locations are renumbered in the
+// order they're first used, and branch targets become offsets from the branch, so inserting a
+// blank line or swapping m[10] for m[50] makes no difference.
+pub fn normalize(instructions: &[Instruction]) -> Vec<String> {
+    let mut locations: HashMap<u32, usize> = HashMap::new();
+
+    instructions.iter().enumerate().map(|(idx, instr)| {
+        if let Some(location) = instr.branch_target() {
+            let target = match parser::target_index(instructions, location) {
+                Some(target) => format!("{:+}", target as i64 - idx as i64),
+                None => "end".to_string()
+            };
+
+            return format!("{} @{}", instr.opcode(), target);
+        }
+
+        if let Some(location) = instr.location() {
+            let next = locations.len();
+            let canonical = *locations.entry(location).or_insert(next);
+            return format!("{} m{}", instr.opcode(), canonical);
+        }
+
+        match instr {
+            Instruction::LDC(ldc) => format!("{} {}", instr.opcode(), ldc.value),
+            Instruction::ADC(adc) => format!("{} {}", instr.opcode(), adc.value),
+            _ => instr.opcode().to_string()
+        }
+    }).collect()
+}
+
+fn ngrams(tokens: &[String], n: usize) -> HashMap<&[String], usize> {
+    let mut counts = HashMap::new();
+
+    // programs shorter than n are treated as a single n-gram
+    for gram in tokens.windows(n.min(tokens.len()).max(1)) {
+        *counts.entry(gram).or_insert(0) += 1;
+    }
+
+    counts
+}
+
+// Scores two normalized programs from 0 (nothing in common) to 1 (identical after normalization),
+// as the size of the intersection of their n-gram multisets over the size of the union.
+pub fn similarity(a: &[String], b: &[String], n: usize) -> f64 {
+    let a_grams = ngrams(a, n);
+    let b_grams = ngrams(b, n);
+
+    let mut shared = 0;
+    let mut total = 0;
+
+    for (gram, a_count) in &a_grams {
+        let b_count = b_grams.get(gram).copied().unwrap_or(0);
+        shared += (*a_count).min(b_count);
+        total += (*a_count).max(b_count);
+    }
+
+    for (gram, b_count) in &b_grams {
+        if !a_grams.contains_key(gram) {
+            total += b_count;
+        }
+    }
+
+    if total == 0 {
+        1.0
+    } else {
+        shared as f64 / total as f64
+    }
+}
+
+// The similarity of two programs, identified by their index.
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub first: usize,
+    pub second: usize,
+    pub score: f64,
+}
+
+// Scores every pair of programs, most similar first.
+pub fn pairwise(programs: &[Vec<String>], n: usize) -> Vec<Pair> {
+    let mut pairs = vec![];
+
+    for first in 0..programs.len() {
+        for second in first + 1..programs.len() {
+            let score = similarity(&programs[first], &programs[second], n);
+            pairs.push(Pair { first, second, score });
+        }
+    }
+
+    pairs.sort_by(|a, b| b.score.total_cmp(&a.score));
+    pairs
+}
+
+pub fn render(names: &[String], pairs: &[Pair]) -> String {
+    let width = pairs.iter().map(|pair| names[pair.first].len()).max().unwrap_or(0);
+
+    pairs.iter()
+        .map(|pair| format!("{:>5.1}%  {:<width$}  {}", pair.score * 100.0, names[pair.first], names[pair.second]))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(program: &str) -> Vec<String> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+        normalize(&result.instructions)
+    }
+
+    #[test]
+    fn it_ignores_cosmetic_changes() {
+        let original = normalized("INP 10\nLDA 10\nBPA 5\nOUT 10\nSTP");
+        let disguised = normalized("\n\nINP 50\n\nLDA 50\nBPA 9\n\nOUT 50\nSTP\n");
+
+        assert!(original == vec!["INP m0", "LDA m0", "BPA @+2", "OUT m0", "STP"]);
+        assert!(original == disguised);
+        assert!(similarity(&original, &disguised, DEFAULT_NGRAM) == 1.0);
+    }
+
+    #[test]
+    fn it_scores_partial_overlap() {
+        let a = normalized("INP 10\nINP 11\nLDA 10\nADD 11\nSTA 12\nOUT 12\nSTP");
+        let b = normalized("INP 10\nINP 11\nLDA 10\nSUB 11\nSTA 12\nOUT 12\nSTP");
+        let c = normalized("LDC 1\nSTA 10\nOUT 10\nLDC 2\nSTA 10\nOUT 10\nSTP");
+
+        let close = similarity(&a, &b, 2);
+        let far = similarity(&a, &c, 2);
+
+        assert!(close > 0.0 && close < 1.0);
+        assert!(far < close);
+    }
+
+    #[test]
+    fn it_sorts_pairs_by_score() {
+        let programs = vec![
+            normalized("INP 10\nOUT 10\nSTP"),
+            normalized("LDC 1\nSTA 10\nOUT 10\nSTP"),
+            normalized("INP 20\nOUT 20\nSTP"),
+        ];
+
+        let pairs = pairwise(&programs, DEFAULT_NGRAM);
+
+        assert!(pairs.len() == 3);
+        assert!((pairs[0].first, pairs[0].second) == (0, 2));
+        assert!(pairs[0].score == 1.0);
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{BTreeSet, HashMap};
+use colored::Colorize;
+use crate::cfg;
+use crate::parser::Instruction;
+
+// Something an instruction can read or write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Var {
+    Accum,
+    Memory(u32),
+}
+
+// The values each instruction reads. Branches read the accumulator to decide where to go.
+pub fn uses(instr: &Instruction) -> Vec<Var> {
+    match instr {
+        Instruction::LDA(lda) => vec![Var::Memory(lda.location)],
+        Instruction::STA(_) => vec![Var::Accum],
+        Instruction::OUT(out) => vec![Var::Memory(out.location)],
+        Instruction::ADC(_) => vec![Var::Accum],
+        Instruction::ADD(add) => vec![Var::Accum, Var::Memory(add.location)],
+        Instruction::SUB(sub) => vec![Var::Accum, Var::Memory(sub.location)],
+        Instruction::MUL(mul) => vec![Var::Accum, Var::Memory(mul.location)],
+        Instruction::DIV(div) => vec![Var::Accum, Var::Memory(div.location)],
+        Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_) => vec![Var::Accum],
+        _ => vec![]
+    }
+}
+
+// The values each instruction writes. Note that OUT loads the location it prints into the
+// accumulator, just like the compiled code does.
+pub fn defs(instr: &Instruction) -> Vec<Var> {
+    match instr {
+        Instruction::LDA(_) | Instruction::LDC(_) | Instruction::OUT(_) => vec![Var::Accum],
+        Instruction::ADC(_) | Instruction::ADD(_) | Instruction::SUB(_) => vec![Var::Accum],
+        Instruction::MUL(_) | Instruction::DIV(_) => vec![Var::Accum],
+        Instruction::STA(sta) => vec![Var::Memory(sta.location)],
+        Instruction::INP(inp) => vec![Var::Memory(inp.location)],
+        _ => vec![]
+    }
+}
+
+// For each instruction, the instructions whose writes may still be visible when it starts
+// executing, grouped by what they wrote.
+pub fn reaching_definitions(instructions: &[Instruction]) -> Vec<HashMap<Var, BTreeSet<usize>>> {
+    let preds = cfg::predecessors(instructions);
+    let mut reaching_in: Vec<HashMap<Var, BTreeSet<usize>>> = vec![HashMap::new(); instructions.len()];
+    let mut reaching_out: Vec<HashMap<Var, BTreeSet<usize>>> = vec![HashMap::new(); instructions.len()];
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for idx in 0..instructions.len() {
+            let mut new_in: HashMap<Var, BTreeSet<usize>> = HashMap::new();
+
+            for pred in &preds[idx] {
+                for (var, def_idxs) in &reaching_out[*pred] {
+                    new_in.entry(*var).or_default().extend(def_idxs);
+                }
+            }
+
+            let mut new_out = new_in.clone();
+
+            for var in defs(&instructions[idx]) {
+                new_out.insert(var, BTreeSet::from([idx]));
+            }
+
+            if new_in != reaching_in[idx] || new_out != reaching_out[idx] {
+                reaching_in[idx] = new_in;
+                reaching_out[idx] = new_out;
+                changed = true;
+            }
+        }
+    }
+
+    reaching_in
+}
+
+// Computes the backward slice of the given instruction: every instruction that can affect the
+// values it reads, either by writing them or by deciding whether the writes happen. The result
+// contains instruction indices and always includes the criterion itself.
+pub fn slice(instructions: &[Instruction], criterion: usize) -> BTreeSet<usize> {
+    let reaching = reaching_definitions(instructions);
+    let control_deps = cfg::control_dependences(instructions);
+    let mut result = BTreeSet::from([criterion]);
+    let mut worklist = vec![criterion];
+
+    while let Some(idx) = worklist.pop() {
+        let mut deps: BTreeSet<usize> = control_deps[idx].clone();
+
+        for var in uses(&instructions[idx]) {
+            if let Some(def_idxs) = reaching[idx].get(&var) {
+                deps.extend(def_idxs);
+            }
+        }
+
+        for dep in deps {
+            if result.insert(dep) {
+                worklist.push(dep);
+            }
+        }
+    }
+
+    result
+}
+
+// Finds the OUT instruction on the given line and slices it.
+pub fn slice_output(instructions: &[Instruction], lineno: usize) -> Result<BTreeSet<usize>, String> {
+    let position = instructions.iter().position(|instr| {
+        instr.lineno() == lineno && matches!(instr, Instruction::OUT(_))
+    });
+
+    match position {
+        Some(idx) => Ok(slice(instructions, idx)),
+        None => Err(format!("No OUT instruction on line {}", lineno))
+    }
+}
+
+// Prints the whole program, highlighting the lines in the slice and dimming the rest.
+pub fn annotate(source: &str, instructions: &[Instruction], slice: &BTreeSet<usize>) -> String {
+    let lines_in_slice: BTreeSet<usize> = slice.iter().map(|idx| instructions[*idx].lineno()).collect();
+    let width = format!("{}", source.lines().count()).len();
+
+    source.lines().enumerate().map(|(idx, line)| {
+        let lineno = idx + 1;
+        let label = format!("{:>width$}.", lineno, width = width);
+
+        if lines_in_slice.contains(&lineno) {
+            format!("{} {} {}", ">".green().bold(), label.blue(), line.trim().bold())
+        } else {
+            format!("  {} {}", label.dimmed(), line.trim().dimmed())
+        }
+    }).collect::<Vec<String>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn slice_lines(program: &str, lineno: usize) -> Vec<usize> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+
+        let slice = slice_output(&result.instructions, lineno).unwrap();
+        slice.iter().map(|idx| result.instructions[*idx].lineno()).collect()
+    }
+
+    #[test]
+    fn it_follows_data_flow() {
+        // line 3 stores into 11, which is never printed
+        let lines = slice_lines("INP 10\nLDC 2\nSTA 11\nLDA 10\nADC 1\nSTA 12\nOUT 12\nSTP", 7);
+        assert!(lines == vec![1, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn it_includes_branches_that_control_the_output() {
+        let lines = slice_lines(
+            "INP 10\nLDA 10\nBPA 6\nLDC 0\nSTA 10\nOUT 10\nINP 11\nSTP",
+            6
+        );
+
+        assert!(lines == vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn it_includes_branches_past_the_end_that_skip_the_output() {
+        let lines = slice_lines("INP 10\nLDA 10\nBPA 99\nOUT 10\nSTP", 4);
+        assert!(lines == vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn it_follows_values_around_loops() {
+        // counts down from the input, printing the final value
+        let lines = slice_lines(
+            "INP 10\nLDC -1\nSTA 11\nLDA 10\nADD 11\nSTA 10\nBPA 4\nLDC 7\nSTA 12\nOUT 10\nSTP",
+            10
+        );
+
+        assert!(lines == vec![1, 2, 3, 4, 5, 6, 7, 10]);
+    }
+
+    #[test]
+    fn it_requires_an_output_on_the_line() {
+        let result = parser::parse("LDC 1\nSTP");
+        assert!(slice_output(&result.instructions, 1).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use serde::Serialize;
+use crate::cfg;
+use crate::parser::Instruction;
+
+// Size and complexity metrics for a program, used to tune exercise difficulty and to spot
+// submissions that are much more complicated than they need to be.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub instructions: usize,
+    pub opcodes: BTreeMap<&'static str, usize>,
+    pub memory_locations: usize,
+    pub branches: usize,
+    pub loops: usize,
+    pub cyclomatic_complexity: usize,
+    pub longest_block: Option<Block>,
+    pub max_loop_depth: usize,
+}
+
+// The line range of a basic block.
+#[derive(Debug, Serialize)]
+pub struct Block {
+    pub instructions: usize,
+    pub first_line: usize,
+    pub last_line: usize,
+}
+
+pub fn stats(instructions: &[Instruction]) -> Stats {
+    let mut opcodes = BTreeMap::new();
+
+    for instr in instructions {
+        *opcodes.entry(instr.opcode()).or_insert(0) += 1;
+    }
+
+    let memory_locations: BTreeSet<u32> = instructions.iter().filter_map(|instr| instr.location()).collect();
+    let branches = instructions.iter().filter(|instr| instr.branch_target().is_some()).count();
+
+    let blocks = cfg::basic_blocks(instructions);
+    let reachable = cfg::reachable(&blocks);
+    let loops = cfg::loops(&blocks);
+
+    // The number of independent paths through the program, i.e. one more than the number of
+    // reachable decisions. Leaving the program by branching past the last line counts as a place to
+    // go, but branches whose edges lead to the same place don't count.
+    let decisions = reachable.iter().filter(|block_idx| {
+        let block = &blocks[**block_idx];
+        block.succs.len() + block.exits as usize > 1
+    }).count();
+
+    // the first of the longest blocks, if there's a tie
+    let longest_block = blocks.iter().min_by_key(|block| Reverse(block.len())).map(|block| {
+        Block {
+            instructions: block.len(),
+            first_line: instructions[block.start].lineno(),
+            last_line: instructions[block.last()].lineno(),
+        }
+    });
+
+    let max_loop_depth = (0..blocks.len()).map(|block_idx| {
+        loops.iter().filter(|l| l.body.contains(&block_idx)).count()
+    }).max().unwrap_or(0);
+
+    Stats {
+        instructions: instructions.len(),
+        opcodes,
+        memory_locations: memory_locations.len(),
+        branches,
+        loops: loops.len(),
+        cyclomatic_complexity: decisions + 1,
+        longest_block,
+        max_loop_depth,
+    }
+}
+
+pub fn render_table(stats: &Stats) -> String {
+    let mut rows: Vec<(String, String)> = vec![
+        ("Instructions".to_string(), stats.instructions.to_string()),
+    ];
+
+    for (opcode, count) in &stats.opcodes {
+        rows.push((format!("  {}", opcode), count.to_string()));
+    }
+
+    rows.push(("Memory locations".to_string(), stats.memory_locations.to_string()));
+    rows.push(("Branches".to_string(), stats.branches.to_string()));
+    rows.push(("Loops".to_string(), stats.loops.to_string()));
+    rows.push(("Cyclomatic complexity".to_string(), stats.cyclomatic_complexity.to_string()));
+
+    if let Some(block) = &stats.longest_block {
+        rows.push((
+            "Longest straight-line block".to_string(),
+            format!("{} (lines {}-{})", block.instructions, block.first_line, block.last_line)
+        ));
+    }
+
+    rows.push(("Max loop nesting depth".to_string(), stats.max_loop_depth.to_string()));
+
+    let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+
+    rows.iter()
+        .map(|(label, value)| format!("{:<width$}  {}", label, value))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn render_json(stats: &Stats) -> String {
+    serde_json::to_string_pretty(stats).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn it_computes_stats_for_straight_line_programs() {
+        let result = parser::parse("LDC 5\nSTA 10\nLDC 2\nSTA 11\nLDA 10\nDIV 11\nSTA 12\nOUT 12\nSTP");
+        let stats = stats(&result.instructions);
+
+        assert!(stats.instructions == 9);
+        assert!(stats.opcodes["STA"] == 3);
+        assert!(stats.memory_locations == 3);
+        assert!(stats.branches == 0);
+        assert!(stats.loops == 0);
+        assert!(stats.cyclomatic_complexity == 1);
+        assert!(stats.longest_block.unwrap().instructions == 9);
+        assert!(stats.max_loop_depth == 0);
+    }
+
+    #[test]
+    fn it_computes_stats_for_nested_loops() {
+        let result = parser::parse(
+            "INP 10\nINP 11\nLDA 11\nADC -1\nSTA 11\nBPA 3\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP"
+        );
+
+        let stats = stats(&result.instructions);
+
+        assert!(stats.branches == 2);
+        assert!(stats.loops == 2);
+        assert!(stats.cyclomatic_complexity == 3);
+        assert!(stats.max_loop_depth == 2);
+
+        let block = stats.longest_block.unwrap();
+        assert!((block.instructions, block.first_line, block.last_line) == (4, 3, 6));
+    }
+
+    #[test]
+    fn it_counts_branches_past_the_end_as_decisions() {
+        let result = parser::parse("INP 10\nLDA 10\nBPA 9\nOUT 10\nSTP");
+        let stats = stats(&result.instructions);
+
+        assert!(stats.branches == 1);
+        assert!(stats.cyclomatic_complexity == 2);
+    }
+}
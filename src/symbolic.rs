@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::cfg;
+use crate::parser::{self, Instruction};
+
+// Symbolic execution of programs without loops. Rather than running a program on particular
+// inputs, it tracks each value as a formula over the values read by INP, so a grader can check
+// eg. an averaging exercise by reading "OUT#1 = (in1 + in2) / 2" instead of trying lots of inputs.
+
+// Programs with branches have one formula per path, and each path is only explored while the
+// number of paths stays below this limit.
+pub const MAX_PATHS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sym {
+    // The nth value read by INP, counting from 1.
+    Input(usize),
+
+    // The garbage value a memory location contains before it's written.
+    Initial(u32),
+
+    // The garbage value the accumulator contains when the program starts.
+    InitialAccum,
+
+    Const(f64),
+    Binary(char, Box<Sym>, Box<Sym>),
+}
+
+impl Sym {
+    // Builds a binary expression, folding constants and dropping identities like x + 0.
+    fn binary(op: char, left: Sym, right: Sym) -> Sym {
+        match (op, &left, &right) {
+            (_, Sym::Const(l), Sym::Const(r)) => Sym::Const(match op {
+                '+' => l + r,
+                '-' => l - r,
+                '*' => l * r,
+                _ => l / r,
+            }),
+
+            ('+', _, Sym::Const(r)) if *r == 0.0 => left,
+            ('+', Sym::Const(l), _) if *l == 0.0 => right,
+            ('-', _, Sym::Const(r)) if *r == 0.0 => left,
+            ('*', _, Sym::Const(r)) if *r == 1.0 => left,
+            ('*', Sym::Const(l), _) if *l == 1.0 => right,
+            ('/', _, Sym::Const(r)) if *r == 1.0 => left,
+
+            // x + -2 reads better as x - 2
+            ('+', _, Sym::Const(r)) if *r < 0.0 => Sym::Binary('-', Box::new(left), Box::new(Sym::Const(-r))),
+
+            _ => Sym::Binary(op, Box::new(left), Box::new(right))
+        }
+    }
+
+    fn precedence(self: &Self) -> u8 {
+        match self {
+            Sym::Binary('+' | '-', _, _) => 1,
+            Sym::Binary(_, _, _) => 2,
+            _ => 3
+        }
+    }
+
+    fn fmt_prec(self: &Self, f: &mut fmt::Formatter<'_>, parent_prec: u8) -> fmt::Result {
+        let needs_parens = self.precedence() < parent_prec;
+
+        if needs_parens {
+            write!(f, "(")?;
+        }
+
+        match self {
+            Sym::Input(n) => write!(f, "in{}", n)?,
+            Sym::Initial(location) => write!(f, "m{}", location)?,
+            Sym::InitialAccum => write!(f, "acc")?,
+            Sym::Const(value) => write!(f, "{}", value)?,
+
+            Sym::Binary(op, left, right) => {
+                let prec = self.precedence();
+                left.fmt_prec(f, prec)?;
+                write!(f, " {} ", op)?;
+
+                // the right operand needs parentheses at equal precedence, eg. a - (b - c)
+                right.fmt_prec(f, prec + 1)?;
+            }
+        }
+
+        if needs_parens {
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sym {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_prec(f, 0)
+    }
+}
+
+// A condition a path depends on, eg. in1 - in2 > 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub expr: Sym,
+    pub comparison: &'static str,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} 0", self.expr, self.comparison)
+    }
+}
+
+// One way through the program: the conditions that lead down it and the values it outputs.
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub conditions: Vec<Condition>,
+    pub outputs: Vec<Sym>,
+}
+
+#[derive(Clone)]
+struct State {
+    pc: Option<usize>,
+    accum: Sym,
+    memory: HashMap<u32, Sym>,
+    inputs: usize,
+    path: Path,
+}
+
+impl State {
+    fn read(self: &Self, location: u32) -> Sym {
+        self.memory.get(&location).cloned().unwrap_or(Sym::Initial(location))
+    }
+}
+
+// Symbolically executes every path through the program. Fails if the program contains a loop or
+// has too many paths.
+pub fn execute(instructions: &[Instruction]) -> Result<Vec<Path>, String> {
+    let blocks = cfg::basic_blocks(instructions);
+
+    if let Some(l) = cfg::loops(&blocks).first() {
+        let lineno = instructions[blocks[l.header].start].lineno();
+        return Err(format!("Symbolic execution only supports programs without loops, but there is a loop starting on line {}", lineno));
+    }
+
+    let mut finished = vec![];
+
+    let mut pending = vec![State {
+        pc: if instructions.len() > 0 { Some(0) } else { None },
+        accum: Sym::InitialAccum,
+        memory: HashMap::new(),
+        inputs: 0,
+        path: Path { conditions: vec![], outputs: vec![] },
+    }];
+
+    while let Some(mut state) = pending.pop() {
+        let idx = match state.pc {
+            Some(idx) => idx,
+            None => {
+                finished.push(state.path);
+                continue;
+            }
+        };
+
+        let next = if idx + 1 < instructions.len() { Some(idx + 1) } else { None };
+        let target = |location: u32| parser::target_index(instructions, location);
+        state.pc = next;
+
+        match &instructions[idx] {
+            Instruction::LDA(lda) => state.accum = state.read(lda.location),
+            Instruction::LDC(ldc) => state.accum = Sym::Const(ldc.value),
+            Instruction::STA(sta) => { state.memory.insert(sta.location, state.accum.clone()); },
+
+            Instruction::INP(inp) => {
+                state.inputs += 1;
+                state.memory.insert(inp.location, Sym::Input(state.inputs));
+            }
+
+            Instruction::OUT(out) => {
+                // like the compiled code, OUT also loads the value into the accumulator
+                state.accum = state.read(out.location);
+                state.path.outputs.push(state.accum.clone());
+            }
+
+            Instruction::ADC(adc) => state.accum = Sym::binary('+', state.accum.clone(), Sym::Const(adc.value)),
+            Instruction::ADD(add) => state.accum = Sym::binary('+', state.accum.clone(), state.read(add.location)),
+            Instruction::SUB(sub) => state.accum = Sym::binary('-', state.accum.clone(), state.read(sub.location)),
+            Instruction::MUL(mul) => state.accum = Sym::binary('*', state.accum.clone(), state.read(mul.location)),
+            Instruction::DIV(div) => state.accum = Sym::binary('/', state.accum.clone(), state.read(div.location)),
+            Instruction::BRU(bru) => state.pc = target(bru.location),
+            Instruction::STP(_) => state.pc = None,
+
+            instr @ (Instruction::BPA(_) | Instruction::BNA(_) | Instruction::BZA(_)) => {
+                let (taken, not_taken) = match instr {
+                    Instruction::BPA(_) => (">", "<="),
+                    Instruction::BNA(_) => ("<", ">="),
+                    _ => ("==", "!=")
+                };
+
+                let jump = target(instr.branch_target().unwrap());
+
+                if let Sym::Const(value) = state.accum {
+                    // the branch always goes the same way
+                    let holds = match taken {
+                        ">" => value > 0.0,
+                        "<" => value < 0.0,
+                        _ => value == 0.0
+                    };
+
+                    if holds {
+                        state.pc = jump;
+                    }
+                } else {
+                    // the branched state is pushed first so that fall-through paths come out first
+                    let mut branched = state.clone();
+                    branched.pc = jump;
+                    branched.path.conditions.push(Condition { expr: state.accum.clone(), comparison: taken });
+                    state.path.conditions.push(Condition { expr: state.accum.clone(), comparison: not_taken });
+                    pending.push(branched);
+                }
+            }
+        }
+
+        pending.push(state);
+
+        if finished.len() + pending.len() > MAX_PATHS {
+            return Err(format!("The program has more than {} paths", MAX_PATHS));
+        }
+    }
+
+    Ok(finished)
+}
+
+pub fn render(paths: &[Path]) -> String {
+    let mut lines = vec![];
+    let single = paths.len() == 1;
+
+    for path in paths {
+        let indent = if single { "" } else { "    " };
+
+        if !single {
+            let conditions: Vec<String> = path.conditions.iter().map(|c| c.to_string()).collect();
+            lines.push(format!("when {}:", conditions.join(" and ")));
+        }
+
+        if path.outputs.len() == 0 {
+            lines.push(format!("{}(no output)", indent));
+        }
+
+        for (idx, output) in path.outputs.iter().enumerate() {
+            lines.push(format!("{}OUT#{} = {}", indent, idx + 1, output));
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbolic(program: &str) -> Result<String, String> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+        execute(&result.instructions).map(|paths| render(&paths))
+    }
+
+    #[test]
+    fn it_prints_formulas_for_straight_line_programs() {
+        let output = symbolic("INP 10\nINP 11\nLDC 2\nSTA 12\nLDA 10\nADD 11\nDIV 12\nSTA 13\nOUT 13\nSTP");
+        assert!(output == Ok("OUT#1 = (in1 + in2) / 2".to_string()));
+    }
+
+    #[test]
+    fn it_treats_uninitialized_locations_as_free_symbols() {
+        let output = symbolic("INP 10\nLDA 10\nSUB 20\nADC -1\nSTA 11\nOUT 11\nOUT 10\nSTP");
+        assert!(output == Ok("OUT#1 = in1 - m20 - 1\nOUT#2 = in1".to_string()));
+    }
+
+    #[test]
+    fn it_splits_on_branches() {
+        let output = symbolic("INP 10\nLDA 10\nBNA 6\nOUT 10\nSTP\nLDC 0\nSUB 10\nSTA 10\nOUT 10\nSTP");
+
+        assert!(output == Ok([
+            "when in1 >= 0:",
+            "    OUT#1 = in1",
+            "when in1 < 0:",
+            "    OUT#1 = 0 - in1",
+        ].join("\n")));
+    }
+
+    #[test]
+    fn it_skips_branches_decided_by_constants() {
+        let output = symbolic("LDC 1\nBPA 5\nINP 10\nOUT 10\nSTP");
+        assert!(output == Ok("(no output)".to_string()));
+    }
+
+    #[test]
+    fn it_rejects_loops() {
+        assert!(symbolic("INP 10\nLDA 10\nADC -1\nSTA 10\nBPA 2\nSTP").is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Helpers shared by the unit tests.
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+// A fresh directory under std::env::temp_dir() for tests that need real files. It's removed when
+// dropped, so tests clean up after themselves even when an assertion fails.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    // Tests run in parallel, so the name is made unique to this process and call.
+    pub fn new(name: &str) -> Self {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("rscc-{}-{}-{}", name, std::process::id(), id));
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir { path }
+    }
+
+    pub fn path(self: &Self) -> &Path {
+        &self.path
+    }
+
+    // Writes a file at the given path inside the directory, creating any directories on the way,
+    // and returns its full path.
+    pub fn write(self: &Self, file: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(self: &mut Self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
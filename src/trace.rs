@@ -0,0 +1,164 @@
+use serde::Serialize;
+use crate::interpreter::{Event, Machine};
+use crate::parser::Instruction;
+
+// Execution traces: one record for every instruction a program executes, with the accumulator
+// before and after, the memory location written and any input or output. Records can be printed
+// as text for reading, or as JSON lines for visualizers and step-through replay. The JSON field
+// names are part of the format and shouldn't change.
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    // Counts executed instructions from 1.
+    pub step: u64,
+
+    // The index of the instruction, as used for debugger addresses.
+    pub address: usize,
+    pub line: usize,
+    pub instruction: String,
+    pub acc_before: f64,
+    pub acc_after: f64,
+    pub write: Option<Write>,
+    pub input: Option<f64>,
+    pub output: Option<f64>,
+    pub halted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Write {
+    pub location: u32,
+    pub value: f64,
+}
+
+// The instruction as it would be written in a program, eg. "LDA 10".
+pub fn instruction_text(instr: &Instruction) -> String {
+    let operand = match instr {
+        Instruction::LDC(ldc) => Some(ldc.value.to_string()),
+        Instruction::ADC(adc) => Some(adc.value.to_string()),
+        _ => instr.location().or(instr.branch_target()).map(|location| location.to_string())
+    };
+
+    match operand {
+        Some(operand) => format!("{} {}", instr.opcode(), operand),
+        None => instr.opcode().to_string()
+    }
+}
+
+// Executes the next instruction, getting a value from read_input if it's an INP, and records what
+// it did. Returns None if the program has already halted.
+pub fn step(machine: &mut Machine, read_input: &mut dyn FnMut() -> f64) -> Option<Record> {
+    let address = machine.pc?;
+    let instr = machine.current()?;
+    let acc_before = machine.accum;
+    let mut input = None;
+    let mut output = None;
+
+    match machine.step() {
+        Event::InputRequest(_) => {
+            let value = read_input();
+            machine.input(value);
+            input = Some(value);
+        }
+
+        Event::Output(value) => output = Some(value),
+        Event::Executed | Event::Halted => {}
+    }
+
+    let write = match instr {
+        Instruction::STA(_) | Instruction::INP(_) => instr.location().map(|location| {
+            Write { location, value: machine.memory[&location] }
+        }),
+
+        _ => None
+    };
+
+    Some(Record {
+        step: machine.steps,
+        address,
+        line: instr.lineno(),
+        instruction: instruction_text(instr),
+        acc_before,
+        acc_after: machine.accum,
+        write,
+        input,
+        output,
+        halted: machine.is_halted(),
+    })
+}
+
+pub fn render_text(record: &Record) -> String {
+    let mut text = format!(
+        "{:>6}  line {:<4} {:<12} acc {} -> {}",
+        record.step, record.line, record.instruction, record.acc_before, record.acc_after
+    );
+
+    if let Some(write) = &record.write {
+        text += &format!("  m[{}] = {}", write.location, write.value);
+    }
+
+    if let Some(value) = record.input {
+        text += &format!("  input {}", value);
+    }
+
+    if let Some(value) = record.output {
+        text += &format!("  output {}", value);
+    }
+
+    if record.halted {
+        text += "  halted";
+    }
+
+    text
+}
+
+// A single line of JSON. Values that JSON can't represent, like NaN and infinity, are null.
+pub fn render_json(record: &Record) -> String {
+    serde_json::to_string(record).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn trace(program: &str, inputs: &[f64]) -> Vec<Record> {
+        let result = parser::parse(program);
+        assert!(result.diagnostics.len() == 0);
+
+        let mut machine = Machine::new(&result.instructions, Some(0));
+        let mut inputs = inputs.iter();
+        let mut records = vec![];
+
+        while let Some(record) = step(&mut machine, &mut || *inputs.next().unwrap()) {
+            records.push(record);
+        }
+
+        records
+    }
+
+    #[test]
+    fn it_records_each_instruction() {
+        let records = trace("INP 10\nLDA 10\nADC 1\nSTA 11\nOUT 11\nSTP", &[4.0]);
+
+        assert!(records.len() == 6);
+        assert!(records[0].input == Some(4.0));
+        assert!(records[0].write == Some(Write { location: 10, value: 4.0 }));
+        assert!((records[2].acc_before, records[2].acc_after) == (4.0, 5.0));
+        assert!(records[3].write == Some(Write { location: 11, value: 5.0 }));
+        assert!(records[4].output == Some(5.0));
+        assert!(records[5].halted);
+        assert!(records.iter().map(|r| r.step).collect::<Vec<u64>>() == vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn it_renders_stable_json() {
+        let records = trace("LDC 2\nSTA 10\nSTP", &[]);
+
+        assert!(render_json(&records[1]) == concat!(
+            r#"{"step":2,"address":1,"line":2,"instruction":"STA 10","acc_before":2.0,"acc_after":2.0,"#,
+            r#""write":{"location":10,"value":2.0},"input":null,"output":null,"halted":false}"#
+        ));
+
+        assert!(render_text(&records[1]) == "     2  line 2    STA 10       acc 2 -> 2  m[10] = 2");
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::{Duration, Instant};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use crate::debugger::{Session, Stop};
+use crate::parser::Instruction;
+
+// A full-screen view of a program running: the source with the next instruction highlighted, a
+// grid of memory locations with recent writes marked, the accumulator and the program's input and
+// output. It runs on the debugger's Session, so it can step backwards as well as forwards, and it
+// works offline, unlike the hosted rsc.js visualizer.
+
+pub const KEYS: &str = "s step  b back  r run/pause  +/- speed  x reset  q quit";
+
+// The delays between instructions when running, slowest first.
+const SPEEDS: [Duration; 6] = [
+    Duration::from_millis(1000),
+    Duration::from_millis(500),
+    Duration::from_millis(200),
+    Duration::from_millis(100),
+    Duration::from_millis(20),
+    Duration::from_millis(0),
+];
+
+// Writes within this many steps of the latest are marked in the memory grid.
+const RECENT_STEPS: u64 = 5;
+
+const CELL_WIDTH: usize = 20;
+
+pub struct App<'a> {
+    session: Session<'a>,
+    source: &'a str,
+    title: String,
+    precision: usize,
+
+    // Every location the program uses, shown in the memory grid even before it's written.
+    locations: BTreeSet<u32>,
+
+    // The step at which each location was last written.
+    written: HashMap<u32, u64>,
+
+    // What the program printed and read, oldest first, with the step each happened at.
+    log: Vec<(u64, String)>,
+
+    // What's been typed so far when an INP is waiting for input.
+    input: Option<String>,
+    error: Option<String>,
+    running: bool,
+    speed: usize,
+    pub quit: bool,
+}
+
+impl<'a> App<'a> {
+    pub fn new(title: &str, source: &'a str, instructions: &'a [Instruction], seed: Option<u64>, precision: usize) -> Self {
+        App {
+            session: Session::new(source, instructions, seed, precision),
+            source,
+            title: title.to_string(),
+            precision,
+            locations: instructions.iter().filter_map(|instr| instr.location()).collect(),
+            written: HashMap::new(),
+            log: vec![],
+            input: None,
+            error: None,
+            running: false,
+            speed: 2,
+            quit: false,
+        }
+    }
+
+    pub fn handle_key(self: &mut Self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+
+        if self.input.is_some() {
+            self.handle_input_key(key);
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('s') | KeyCode::Char(' ') | KeyCode::Right => {
+                self.running = false;
+                self.step();
+            }
+
+            KeyCode::Char('b') | KeyCode::Left => {
+                self.running = false;
+                self.back();
+            }
+
+            KeyCode::Char('r') | KeyCode::Enter => self.running = !self.running && !self.session.machine.is_halted(),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            KeyCode::Char('-') => self.speed = self.speed.saturating_sub(1),
+            KeyCode::Char('x') => self.reset(),
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            _ => {}
+        }
+    }
+
+    fn handle_input_key(self: &mut Self, key: KeyEvent) {
+        let text = self.input.as_mut().unwrap();
+
+        match key.code {
+            KeyCode::Char(c) if c.is_ascii_digit() || "+-.eE".contains(c) => text.push(c),
+            KeyCode::Backspace => { text.pop(); }
+            KeyCode::Esc => {
+                self.input = None;
+                self.running = false;
+            }
+
+            KeyCode::Enter => match text.parse::<f64>() {
+                Ok(value) => {
+                    self.input = None;
+                    self.error = None;
+                    self.session.inputs.push_back(value);
+
+                    // finish the INP straight away, which logs the value
+                    self.step();
+                }
+
+                Err(_) => self.error = Some(format!("'{}' is not a number", text))
+            },
+
+            _ => {}
+        }
+    }
+
+    // Runs the next instruction when running, if it's time to.
+    pub fn tick(self: &mut Self) {
+        if self.running && self.input.is_none() {
+            self.step();
+        }
+    }
+
+    fn delay(self: &Self) -> Duration {
+        SPEEDS[self.speed]
+    }
+
+    fn step(self: &mut Self) {
+        let written = match self.session.machine.current() {
+            Some(instr @ (Instruction::STA(_) | Instruction::INP(_))) => instr.location(),
+            _ => None
+        };
+
+        let steps = self.session.machine.steps;
+        let queued = self.session.inputs.front().copied();
+        let queue_len = self.session.inputs.len();
+        let stop = self.session.step(&mut || None);
+        let step = self.session.machine.steps;
+
+        // typed values are queued before the INP runs, and stepping back puts them back in the
+        // queue, so every value INP reads comes from there
+        if let (Some(value), true) = (queued, self.session.inputs.len() < queue_len) {
+            self.log.push((step, format!("in  {}", value)));
+        }
+
+        for value in self.session.outputs.drain(..) {
+            self.log.push((step, format!("out {:.*}", self.precision, value)));
+        }
+
+        if let (Some(location), true) = (written, self.session.machine.steps > steps) {
+            self.written.insert(location, self.session.machine.steps);
+        }
+
+        match stop {
+            Stop::NeedsInput(_) => self.input = Some(String::new()),
+            Stop::Halted => self.running = false,
+            _ => {}
+        }
+    }
+
+    fn back(self: &mut Self) {
+        self.input = None;
+        self.session.reverse_step();
+
+        // forget marks for writes, and lines for input and output, that have been undone
+        let steps = self.session.machine.steps;
+        self.written.retain(|_, step| *step <= steps);
+        self.log.retain(|(step, _)| *step <= steps);
+    }
+
+    fn reset(self: &mut Self) {
+        self.session.restart();
+        self.session.inputs.clear();
+        self.written.clear();
+        self.log.clear();
+        self.input = None;
+        self.error = None;
+        self.running = false;
+    }
+
+    fn status(self: &Self) -> &'static str {
+        if self.session.machine.is_halted() {
+            "halted"
+        } else if self.input.is_some() {
+            "waiting for input"
+        } else if self.running {
+            "running"
+        } else {
+            "paused"
+        }
+    }
+
+    pub fn draw(self: &Self, frame: &mut Frame) {
+        let [main, io, keys] = Layout::vertical([Constraint::Min(6), Constraint::Length(8), Constraint::Length(1)]).areas(frame.area());
+        let [source, side] = Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(main);
+        let [accum, memory] = Layout::vertical([Constraint::Length(3), Constraint::Min(3)]).areas(side);
+
+        self.draw_source(frame, source);
+        self.draw_accum(frame, accum);
+        self.draw_memory(frame, memory);
+        self.draw_io(frame, io);
+
+        let speed = format!("  delay {}ms", self.delay().as_millis());
+        frame.render_widget(Paragraph::new(Line::from(vec![Span::raw(KEYS), Span::styled(speed, Style::default().fg(Color::DarkGray))])), keys);
+    }
+
+    fn draw_source(self: &Self, frame: &mut Frame, area: Rect) {
+        let current = self.session.machine.current().map(|instr| instr.lineno());
+
+        let lines: Vec<Line> = self.source.lines().enumerate().map(|(idx, text)| {
+            let lineno = idx + 1;
+            let text = format!("{:>3} {}", lineno, text);
+
+            if Some(lineno) == current {
+                Line::from(Span::styled(format!("{:<width$}", text, width = area.width as usize), Style::default().bg(Color::Yellow).fg(Color::Black)))
+            } else {
+                Line::from(text)
+            }
+        }).collect();
+
+        // keep the next instruction in the middle of the pane
+        let height = area.height.saturating_sub(2) as usize;
+        let scroll = current.unwrap_or(0).saturating_sub(height / 2 + 1);
+
+        let title = format!(" {} — step {}, {} ", self.title, self.session.machine.steps, self.status());
+        let block = Block::default().borders(Borders::ALL).title(title);
+        frame.render_widget(Paragraph::new(lines).block(block).scroll((scroll as u16, 0)), area);
+    }
+
+    fn draw_accum(self: &Self, frame: &mut Frame, area: Rect) {
+        let text = format!("acc = {:.*}", self.precision, self.session.machine.accum);
+        frame.render_widget(Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(" Accumulator ")), area);
+    }
+
+    fn draw_memory(self: &Self, frame: &mut Frame, area: Rect) {
+        let memory = &self.session.machine.memory;
+        let steps = self.session.machine.steps;
+        let columns = (area.width.saturating_sub(2) as usize / CELL_WIDTH).max(1);
+        let locations: BTreeSet<u32> = self.locations.iter().chain(memory.keys()).copied().collect();
+        let locations: Vec<u32> = locations.into_iter().collect();
+
+        let lines: Vec<Line> = locations.chunks(columns).map(|row| {
+            let cells: Vec<Span> = row.iter().map(|location| {
+                let value = match memory.get(location) {
+                    Some(value) => format!("{:.*}", self.precision, value),
+                    None => "?".to_string()
+                };
+
+                let text = format!("{:>6} {:<width$}", format!("m[{}]", location), value, width = CELL_WIDTH - 7);
+
+                let style = match self.written.get(location) {
+                    Some(step) if *step == steps => Style::default().fg(Color::Black).bg(Color::Green).add_modifier(Modifier::BOLD),
+                    Some(step) if steps - step < RECENT_STEPS => Style::default().fg(Color::Green),
+                    _ => Style::default()
+                };
+
+                Span::styled(text, style)
+            }).collect();
+
+            Line::from(cells)
+        }).collect();
+
+        frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Memory ")), area);
+    }
+
+    fn draw_io(self: &Self, frame: &mut Frame, area: Rect) {
+        let mut lines: Vec<Line> = self.log.iter().map(|(_, line)| Line::from(line.as_str())).collect();
+
+        if let Some(text) = &self.input {
+            let location = self.session.machine.current().and_then(|instr| instr.location()).unwrap_or(0);
+
+            lines.push(Line::from(vec![
+                Span::styled(format!("Input for m[{}]: ", location), Style::default().fg(Color::Cyan)),
+                Span::raw(text.as_str()),
+                Span::styled("_", Style::default().add_modifier(Modifier::SLOW_BLINK)),
+            ]));
+        }
+
+        if let Some(error) = &self.error {
+            lines.push(Line::from(Span::styled(error.as_str(), Style::default().fg(Color::Red))));
+        }
+
+        // show the most recent lines
+        let height = area.height.saturating_sub(2) as usize;
+        let scroll = lines.len().saturating_sub(height);
+
+        frame.render_widget(Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" I/O ")).scroll((scroll as u16, 0)), area);
+    }
+}
+
+// Takes over the terminal until the user quits.
+pub fn run(app: &mut App) -> Result<(), String> {
+    let mut terminal = ratatui::try_init().map_err(|e| e.to_string())?;
+    let result = event_loop(&mut terminal, app);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> Result<(), String> {
+    let mut last_tick = Instant::now();
+
+    while !app.quit {
+        terminal.draw(|frame| app.draw(frame)).map_err(|e| e.to_string())?;
+
+        let timeout = if app.running { app.delay().saturating_sub(last_tick.elapsed()) } else { Duration::from_millis(250) };
+
+        if event::poll(timeout).map_err(|e| e.to_string())? {
+            if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key);
+                }
+            }
+        }
+
+        if app.running && last_tick.elapsed() >= app.delay() {
+            app.tick();
+            last_tick = Instant::now();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+    use crate::parser;
+
+    const PROGRAM: &str = "INP 10\nLDA 10\nADC 1\nSTA 11\nOUT 11\nSTP";
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) };
+            app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+        }
+    }
+
+    fn screen(app: &App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        buffer.content().chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn it_steps_through_input_and_output() {
+        let result = parser::parse(PROGRAM);
+        let mut app = App::new("prog.rsc", PROGRAM, &result.instructions, Some(0), 2);
+
+        press(&mut app, "s");
+        assert!(app.input == Some("".to_string()));
+        assert!(screen(&app).contains("Input for m[10]:"));
+
+        press(&mut app, "41\n");
+        assert!(app.written.get(&10) == Some(&1));
+
+        press(&mut app, "sssss");
+        assert!(app.session.machine.is_halted());
+        assert!(app.log == vec![(1, "in  41".to_string()), (5, "out 42.00".to_string())]);
+
+        let screen = screen(&app);
+        assert!(screen.contains("step 6, halted"));
+        assert!(screen.contains("m[11] 42.00"));
+    }
+
+    #[test]
+    fn it_steps_back_and_resets() {
+        let result = parser::parse(PROGRAM);
+        let mut app = App::new("prog.rsc", PROGRAM, &result.instructions, Some(0), 2);
+
+        press(&mut app, "s7\nsss");
+        assert!(app.written.contains_key(&11));
+
+        press(&mut app, "b");
+        assert!(!app.written.contains_key(&11));
+        assert!(app.session.machine.current().unwrap().lineno() == 4);
+
+        press(&mut app, "x");
+        assert!(app.session.machine.steps == 0);
+        assert!(app.log.len() == 0);
+        assert!(screen(&app).contains("m[10] ?"));
+    }
+
+    #[test]
+    fn it_steps_back_over_input_and_output() {
+        let result = parser::parse(PROGRAM);
+        let mut app = App::new("prog.rsc", PROGRAM, &result.instructions, Some(0), 2);
+
+        press(&mut app, "s7\nssss");
+        assert!(app.log == vec![(1, "in  7".to_string()), (5, "out 8.00".to_string())]);
+
+        press(&mut app, "b");
+        assert!(app.log == vec![(1, "in  7".to_string())]);
+        assert!(!screen(&app).contains("out 8.00"));
+
+        press(&mut app, "bbbb");
+        assert!(app.log.len() == 0);
+
+        // the undone INP takes the same value again without asking for it
+        press(&mut app, "s");
+        assert!(app.input.is_none());
+        assert!(app.log == vec![(1, "in  7".to_string())]);
+
+        press(&mut app, "ssss");
+        assert!(app.log == vec![(1, "in  7".to_string()), (5, "out 8.00".to_string())]);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use colored::Colorize;
+use crate::parser::Instruction;
+
+// The lines that read from and write to a single memory location.
+#[derive(Debug, Default)]
+pub struct Reference {
+    pub location: u32,
+    pub reads: Vec<usize>,
+    pub writes: Vec<usize>,
+}
+
+impl Reference {
+    // Read but never written, so the program only ever sees the garbage it started with.
+    pub fn is_only_read(self: &Self) -> bool {
+        self.writes.len() == 0
+    }
+
+    // Written but never read, so the value is thrown away.
+    pub fn is_only_written(self: &Self) -> bool {
+        self.reads.len() == 0
+    }
+}
+
+// Builds the cross-reference table for every memory location the program touches, sorted by
+// location.
+pub fn xref(instructions: &[Instruction]) -> Vec<Reference> {
+    let mut refs: BTreeMap<u32, Reference> = BTreeMap::new();
+
+    for instr in instructions {
+        let location = match instr.location() {
+            Some(location) => location,
+            None => continue
+        };
+
+        let reference = refs.entry(location).or_insert_with(|| {
+            Reference { location, ..Reference::default() }
+        });
+
+        match instr {
+            Instruction::STA(_) | Instruction::INP(_) => reference.writes.push(instr.lineno()),
+            _ => reference.reads.push(instr.lineno())
+        }
+    }
+
+    refs.into_values().collect()
+}
+
+fn format_lines(lines: &[usize]) -> String {
+    if lines.len() == 0 {
+        "-".to_string()
+    } else {
+        lines.iter().map(|lineno| lineno.to_string()).collect::<Vec<String>>().join(", ")
+    }
+}
+
+pub fn render(refs: &[Reference]) -> String {
+    let reads: Vec<String> = refs.iter().map(|reference| format_lines(&reference.reads)).collect();
+    let writes: Vec<String> = refs.iter().map(|reference| format_lines(&reference.writes)).collect();
+    let read_width = reads.iter().map(|s| s.len()).max().unwrap_or(0).max("READ BY".len());
+    let write_width = writes.iter().map(|s| s.len()).max().unwrap_or(0).max("WRITTEN BY".len());
+
+    let mut lines = vec![
+        format!("{:<8}  {:<read_width$}  {:<write_width$}", "LOCATION", "READ BY", "WRITTEN BY").bold().to_string()
+    ];
+
+    for (idx, reference) in refs.iter().enumerate() {
+        let flag = if reference.is_only_read() {
+            "only read".yellow().to_string()
+        } else if reference.is_only_written() {
+            "only written".yellow().to_string()
+        } else {
+            "".to_string()
+        };
+
+        let line = format!(
+            "{:<8}  {:<read_width$}  {:<write_width$}  {}",
+            reference.location, reads[idx], writes[idx], flag
+        );
+
+        lines.push(line.trim_end().to_string());
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn it_collects_reads_and_writes() {
+        let result = parser::parse("INP 10\nLDA 10\nADD 11\nSTA 12\nSTA 10\nOUT 10\nSTP");
+        let refs = xref(&result.instructions);
+
+        assert!(refs.len() == 3);
+
+        assert!(refs[0].location == 10);
+        assert!(refs[0].reads == vec![2, 6]);
+        assert!(refs[0].writes == vec![1, 5]);
+        assert!(!refs[0].is_only_read() && !refs[0].is_only_written());
+
+        assert!(refs[1].location == 11);
+        assert!(refs[1].is_only_read());
+
+        assert!(refs[2].location == 12);
+        assert!(refs[2].is_only_written());
+    }
+}